@@ -0,0 +1,63 @@
+use std::{hint::black_box, sync::atomic::AtomicBool};
+
+use confour::{
+    bench::positions,
+    board::State,
+    eval::{evaluator::{Evaluator, Windows}, minimax::SearchContext, params::Params, score::Score, search::SearchStats, table::TranspositionTable},
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// Returns a middle game position of the benchmark suite
+fn middle_game() -> State {
+    positions()
+        .into_iter()
+        .find(|(name, _)| *name == "middle")
+        .map(|(_, state)| state)
+        .expect("the suite has a middle game position")
+}
+
+fn bench_drop(c: &mut Criterion) {
+    let state = middle_game();
+
+    c.bench_function("drop", |b| {
+        b.iter(|| {
+            let mut state = state.clone();
+            for col in 0 .. state.cols() {
+                if state.is_valid(col) {
+                    state.drop(black_box(col), state.player());
+                }
+            }
+            state
+        })
+    });
+}
+
+fn bench_get_winner(c: &mut Criterion) {
+    let state = middle_game();
+
+    c.bench_function("get_winner", |b| b.iter(|| black_box(&state).get_winner()));
+}
+
+fn bench_evaluate(c: &mut Criterion) {
+    let state = middle_game();
+
+    c.bench_function("evaluate (weights)", |b| b.iter(|| black_box(&state).evaluate(&Params::DEFAULT)));
+    c.bench_function("evaluate (window)", |b| b.iter(|| Windows.evaluate(black_box(&state), state.player())));
+}
+
+fn bench_negamax(c: &mut Criterion) {
+    let state = middle_game();
+    let tt = TranspositionTable::with_entries(1 << 16);
+    let (stop, stats) = (AtomicBool::new(false), SearchStats::default());
+    let ctx = SearchContext { evaluator: &Windows, tt: &tt, stop: &stop, stats: &stats, deadline: None, history: &[] };
+
+    c.bench_function("negamax (depth 4)", |b| {
+        b.iter(|| {
+            tt.clear();
+            state.negamax(black_box(4), &ctx, &mut vec![], &mut vec![], -Score::INFINITY, Score::INFINITY)
+        })
+    });
+}
+
+criterion_group!(benches, bench_drop, bench_get_winner, bench_evaluate, bench_negamax);
+criterion_main!(benches);
@@ -0,0 +1,159 @@
+use std::{fmt, time::{Duration, Instant}};
+
+use crate::{
+    board::{Board, Move, State},
+    eval::{evaluator::Windows, search::{SearchLimits, SearchResult}, table::TranspositionTable},
+};
+
+/// The default depth every position of the suite is searched to
+pub const BENCH_DEPTH: usize = 6;
+
+/// The number of transposition table entries of every search,
+/// fixed so that the node counts do not depend on the defaults
+pub const BENCH_ENTRIES: usize = 1 << 18;
+
+/// The positions searched by the benchmark (on the standard board),
+/// given by their name and the moves leading to them
+pub const SUITE: [(&str, &str); 8] = [
+    ("opening", "4"),
+    ("early", "4 4 3 5"),
+    ("early", "4 3 4 4 5 2"),
+    ("middle", "4 4 4 4 3 5 3 3 5 2 2 6"),
+    ("middle", "4 2 2 7 2 5 6 7 6 2 1 6 1 3"),
+    ("late", "3 2 6 1 6 5 3 7 1 1 1 1 1 2 7 3 5 5 5 7 3 3"),
+    ("end", "1 4 7 1 3 4 4 5 7 3 4 1 1 3 1 7 7 6 5 7 1 6 6 4 6 7 4 6"),
+    ("end", "6 5 4 5 1 4 7 2 1 2 6 1 4 1 2 7 7 2 2 2 1 7 7 6 6 7 1 4 6 4"),
+];
+
+/// The result of the benchmark
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchResult {
+    /// the results of the positions, in the order of `SUITE`
+    pub results: Vec<SearchResult>,
+
+    /// the node counts of all positions summed up, i.e. the number
+    /// of nodes visited, counting positions visited again each time
+    pub nodes: u64,
+
+    pub elapsed: Duration,
+
+    /// a hash of every position's node count, best move and score,
+    /// changing with (almost) any change to the search
+    pub signature: u64,
+}
+
+impl BenchResult {
+    /// Returns the number of positions searched per second
+    pub fn nps(&self) -> u64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => (self.nodes as f64 / secs) as u64,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for BenchResult {
+    /// Display the totals as a single line, e.g.
+    /// "nodes 1234567 time 2345ms nps 526467 signature 7f3a09c2b14e5d61"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "nodes {} time {}ms nps {} signature {:016x}",
+            self.nodes,
+            self.elapsed.as_millis(),
+            self.nps(),
+            self.signature,
+        )
+    }
+}
+
+/// Returns the positions of the suite (see `SUITE`)
+pub fn positions() -> Vec<(&'static str, State)> {
+    SUITE
+        .iter()
+        .map(|&(name, moves)| {
+            let mut board = Board::new();
+            for mv in moves.split_whitespace() {
+                let mv = mv.parse::<Move>().expect("the suite is in move notation");
+                assert!(board.play(mv), "the suite contains legal moves only");
+            }
+
+            (name, board.state().clone())
+        })
+        .collect()
+}
+
+/// Searches every position of the suite to the given depth, on a single
+/// thread with a fresh transposition table each, so that the node counts
+/// (and thus the signature) only change if the search itself does. The
+/// given callback is called with the result of every position.
+pub fn run(depth: usize, mut progress: impl FnMut(&str, &SearchResult)) -> BenchResult {
+    let start = Instant::now();
+    let mut results = vec![];
+
+    for (name, state) in positions() {
+        let tt = TranspositionTable::with_entries(BENCH_ENTRIES);
+        let result = state.best(SearchLimits::depth(depth), state.player(), &Windows, &tt, 1);
+
+        progress(name, &result);
+        results.push(result);
+    }
+
+    let nodes = results.iter().map(|result| result.nodes).sum();
+    let signature = signature(&results);
+
+    BenchResult { results, nodes, elapsed: start.elapsed(), signature }
+}
+
+/// Returns the (FNV-1a) hash of the node counts, best
+/// moves and scores of the given results
+fn signature(results: &[SearchResult]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+
+    for result in results {
+        let best = result.best_move.map_or(String::new(), |mv| mv.to_string());
+        let values = [result.nodes.to_le_bytes(), (result.score.0 as i64).to_le_bytes()];
+
+        for byte in values.iter().flatten().chain(best.as_bytes()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    hash
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests that every position of the suite is still being played
+    fn test_positions() {
+        for (name, state) in positions() {
+            assert!(
+                !state.is_terminal(),
+                "Expected the {} position to be playable, got\n{}",
+                name, state
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that the benchmark is deterministic
+    fn test_signature() {
+        let (a, b) = (run(3, |_, _| ()), run(3, |_, _| ()));
+
+        assert_eq!(
+            (a.nodes, a.signature), (b.nodes, b.signature),
+            "Expected the same nodes and signature, got {} and {}",
+            a, b
+        );
+
+        assert!(
+            a.results.len() == SUITE.len() && a.results.iter().all(|result| result.nodes > 0),
+            "Expected every position to be searched, got {:?}",
+            a.results
+        );
+    }
+}
@@ -1,4 +1,6 @@
-use std::{fmt, hash::{DefaultHasher, Hash, Hasher}, ops::{Index, IndexMut}};
+use std::{fmt, hash::{DefaultHasher, Hash, Hasher}, ops::{Index, IndexMut}, str::FromStr};
+
+use crate::{detect::WIN_LENGTH, eval::random::{splitmix, stable_hash}, rules::{Rules, Variant}};
 
 /// Defines which player (in the minimax algorithm)
 /// is favored by a high (positive) score.
@@ -8,23 +10,72 @@ pub const MAXIMIZER: Player = Player::Red;
 /// is favored by a low (negative) score.
 pub const MINIMIZER: Player = Player::Yellow;
 
+/// The number of rows on a standard (7x6) board
 pub const ROWS: usize = 6;
+
+/// The number of columns on a standard (7x6) board
 pub const COLS: usize = 7;
 
+/// The smallest number of rows or columns a board may have
+pub const MIN_DIMENSION: usize = 4;
+
+/// The largest number of rows or columns a board may have
+pub const MAX_DIMENSION: usize = 10;
+
+/// The seed of the Zobrist keys (see `ZOBRIST`)
+const ZOBRIST_SEED: u64 = 0x0063_6F6E_666F_7572;
+
+/// The random keys of the cells (one per row, column and player) the
+/// canonical key is made of (see `State::canonical_hash`). They are
+/// derived from a fixed seed, so that keys stay the same across runs
+/// and builds and can be written to files (caches, tablebases).
+const ZOBRIST: [u64; MAX_DIMENSION * MAX_DIMENSION * MAX_PLAYERS] = {
+    let mut keys = [0; MAX_DIMENSION * MAX_DIMENSION * MAX_PLAYERS];
+    let mut i = 0;
+    while i < keys.len() {
+        keys[i] = splitmix(ZOBRIST_SEED.wrapping_add(i as u64));
+        i += 1;
+    }
+    keys
+};
+
+/// The number of times a position has to occur
+/// for the game to be drawn by repetition (PopOut)
+pub const REPETITION_LIMIT: usize = 3;
+
 /// A shallow wrapper around the State struct
-/// for the current/active/etc. state of the board
+/// for the current/active/etc. state of the board,
+/// keeping track of the moves played so far
 pub struct Board {
-    state: State
+    state: State,
+
+    /// The moves played so far, in order
+    moves: Vec<Move>,
+
+    /// The hashes of all positions that occurred
+    /// so far, including the current one
+    history: Vec<u64>,
 }
 
 impl Board {
-    /// Creates a new (empty) board state and wraps it
-    /// in a Board struct
+    /// Creates a new (empty) board state of the standard
+    /// size and wraps it in a Board struct
     pub fn new() -> Board {
+        Board::with_size(ROWS, COLS)
+    }
+
+    /// Creates a new (empty) board state with the given
+    /// dimensions and wraps it in a Board struct
+    pub fn with_size(rows: usize, cols: usize) -> Board {
+        Board::from_state(State::with_size(rows, cols))
+    }
+
+    /// Wraps the given state in a Board struct
+    pub fn from_state(state: State) -> Board {
         Board {
-            state: State {
-                data: vec![vec![Cell::Empty; COLS]; ROWS]
-            }
+            history: vec![state.hash_value()],
+            moves: Vec::new(),
+            state,
         }
     }
 
@@ -35,6 +86,57 @@ impl Board {
     pub fn state_mut(&mut self) -> &mut State {
         &mut self.state
     }
+
+    /// Returns the moves played so far
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// Returns the hashes of all positions that
+    /// occurred so far, including the current one
+    pub fn history(&self) -> &[u64] {
+        &self.history
+    }
+
+    /// Plays the given move for the current player and
+    /// records it. Returns false (and does nothing) if the
+    /// move is not legal in the current state
+    pub fn play(&mut self, mv: Move) -> bool {
+        if !self.state.is_legal(mv) {
+            return false;
+        }
+
+        self.state.play(mv);
+        self.moves.push(mv);
+        self.history.push(self.state.hash_value());
+        true
+    }
+
+    /// Returns true if the current position has occurred
+    /// at least `REPETITION_LIMIT` times (i.e. the game
+    /// is drawn by repetition)
+    pub fn is_repetition(&self) -> bool {
+        let current = self.state.hash_value();
+        self.history.iter().filter(|&&hash| hash == current).count() >= REPETITION_LIMIT
+    }
+
+    /// Returns whether the game is over, either because
+    /// the state is terminal or by repetition
+    pub fn is_over(&self) -> bool {
+        self.state.is_terminal() || self.is_repetition()
+    }
+
+    /// Returns the moves played so far in move notation,
+    /// separated by spaces (e.g. "4 4 3 p4")
+    pub fn notation(&self) -> String {
+        self.moves.iter().map(|mv| mv.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Board::new()
+    }
 }
 
 impl fmt::Display for Board {
@@ -47,82 +149,261 @@ impl fmt::Display for Board {
 
 /// The state of any connect four board/game. Not necessarily 
 /// valid or current
-#[derive(Clone, PartialEq, Eq, Hash)]
+#[derive(Clone)]
 pub struct State {
-    pub data: Vec<Vec<Cell>>
+    pub data: Vec<Vec<Cell>>,
+
+    /// The number of pieces in a row needed to win
+    pub win_length: usize,
+
+    /// The variant (rule set) the game is played with
+    pub variant: Variant,
+
+    /// The number of pieces each player collected (Pop 10)
+    pub captured: [u8; MAX_PLAYERS],
+
+    /// The number of players taking turns (2 or 3)
+    pub players: usize,
+
+    /// Whether the board wraps around horizontally (i.e. the
+    /// last column is adjacent to the first one), so that
+    /// lines can continue across the edge
+    pub cylinder: bool,
+
+    /// The player whose turn it is
+    pub turn: Player,
+
+    /// The cell the last move dropped a piece into, `None` if the
+    /// last move popped a piece or the board was set up (or edited)
+    /// directly. Any line completed by a drop runs through this cell,
+    /// so a game that wasn't over before the drop is decided by
+    /// checking this cell only (see `connected_winner`).
+    pub last: Option<(usize, usize)>,
 }
 
 impl State {
     /// Create a new state with an empty board
+    /// of the standard size
     pub fn new() -> State {
+        State::with_size(ROWS, COLS)
+    }
+
+    /// Create a new state with an empty board
+    /// of the given dimensions
+    pub fn with_size(rows: usize, cols: usize) -> State {
         State {
-            data: vec![vec![Cell::Empty; COLS]; ROWS]
+            data: vec![vec![Cell::Empty; cols]; rows],
+            win_length: WIN_LENGTH,
+            variant: Variant::Standard,
+            captured: [0; MAX_PLAYERS],
+            players: 2,
+            cylinder: false,
+            turn: Player::Red,
+            last: None,
         }
     }
 
+    /// Changes the number of pieces in a row needed to
+    /// win (e.g. 3 for "Connect-3") and returns the state
+    pub fn with_win_length(mut self, win_length: usize) -> State {
+        self.win_length = win_length;
+        self
+    }
+
+    /// Changes the variant (rule set) of the game, prepares
+    /// the state accordingly (see `Rules::setup`) and returns it
+    pub fn with_variant(mut self, variant: Variant) -> State {
+        self.variant = variant;
+        variant.rules().setup(self)
+    }
+
+    /// Changes the number of players taking turns
+    /// and returns the state
+    pub fn with_players(mut self, players: usize) -> State {
+        self.players = players;
+        self.turn = Player::from_index(self.moves() % players);
+        self
+    }
+
+    /// Makes the board wrap around horizontally (or not)
+    /// and returns the state
+    pub fn with_cylinder(mut self, cylinder: bool) -> State {
+        self.cylinder = cylinder;
+        self
+    }
+
+    /// Returns the rules of the game's variant
+    pub fn rules(&self) -> &'static dyn Rules {
+        self.variant.rules()
+    }
+
     /// A simple wrapper around the `hash` method
     pub fn hash_value(&self) -> u64 {
         let mut hasher = DefaultHasher::new();
         self.hash(&mut hasher);
-        return hasher.finish();
+        hasher.finish()
+    }
+
+    /// Returns a hash that is the same for all positions that
+    /// are equivalent by symmetry, i.e. for mirrored positions
+    /// and (on a cylinder) for positions rotated by any number
+    /// of columns. Used as key of the transposition table. As a
+    /// Zobrist key of fixed random numbers (see `ZOBRIST`), it is
+    /// stable across runs and builds, unlike `hash_value`.
+    pub fn canonical_hash(&self) -> u64 {
+        let cols = self.cols();
+        let rotations = if self.cylinder { cols } else { 1 };
+
+        (0 .. rotations)
+            .flat_map(|rotation| [
+                self.hash_transformed(|col| (col + rotation) % cols),
+                self.hash_transformed(|col| cols - 1 - (col + rotation) % cols),
+            ])
+            .min()
+            .unwrap_or_else(|| self.hash_transformed(|col| col))
+    }
+
+    /// Returns the Zobrist key of the state as if every column `col`
+    /// was replaced by column `transform(col)`, where `transform` is
+    /// one of the board's symmetries. The board size, the rules, the
+    /// collected pieces and the player to move are mixed into the key
+    /// of the cells, so that boards of different sizes never share one.
+    fn hash_transformed(&self, transform: impl Fn(usize) -> usize) -> u64 {
+        let mut key = 0;
+
+        for (r, row) in self.data.iter().enumerate() {
+            for col in 0 .. row.len() {
+                if let Some(player) = row[transform(col)].player() {
+                    key ^= ZOBRIST[(r * MAX_DIMENSION + col) * MAX_PLAYERS + player.index()];
+                }
+            }
+        }
+
+        let [red, yellow, green] = self.captured;
+        let rules = [self.win_length as u8, self.variant as u8, self.players as u8, self.cylinder as u8, self.turn.index() as u8, red, yellow, green];
+        key ^ stable_hash([self.rows() as u64, self.cols() as u64, u64::from_le_bytes(rules)])
     }
 
-    /// Create a new state from a 2D vector of cells
+    /// Create a new state from a 2D vector of cells,
+    /// deriving the current player from the number of
+    /// pieces on the board
     pub fn from_data(data: Vec<Vec<Cell>>) -> State {
-        State {
-            data
+        let mut state = State {
+            data,
+            win_length: WIN_LENGTH,
+            variant: Variant::Standard,
+            captured: [0; MAX_PLAYERS],
+            players: 2,
+            cylinder: false,
+            turn: Player::Red,
+            last: None,
+        };
+
+        // Designates the first player as Player::Red
+        if !state.moves().is_multiple_of(2) {
+            state.turn = Player::Yellow;
         }
+
+        state
+    }
+
+    /// Returns the number of rows on the board
+    pub fn rows(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the number of columns on the board
+    pub fn cols(&self) -> usize {
+        self.data.first().map_or(0, |row| row.len())
     }
 
     /// Returns the playe whose turn it is
     pub fn player(&self) -> Player {
-        // Designates the first player as Player::Red
-        if self.moves() % 2 == 0 {
-            Player::Red
-        } else {
-            Player::Yellow
-        }
+        self.turn
     }
 
-    /// Returns the number of moves made so far
+    /// Returns the number of pieces on the board (i.e. the
+    /// number of moves made so far, if no pieces were popped)
     pub fn moves(&self) -> usize {
-        self.data.iter().flatten().filter(|cell| !cell.is_empty()).collect::<Vec<_>>().len()
+        self.data.iter().flatten().filter(|cell| !cell.is_empty()).count()
     }
 
     /// Returns true if the board is full
     pub fn is_full(&self) -> bool {
-        self.data.iter().all(|row| row.iter().all(|cell| *cell != Cell::Empty))
+        // pieces are stacked, so only the top row needs to be checked
+        self.data[0].iter().all(|cell| !cell.is_empty())
     }
 
     /// Retrusn whether the game is over
-    /// (i.e. the board is full or there is a winner)
+    /// (i.e. there is a winner or no moves are left)
     pub fn is_terminal(&self) -> bool {
-        return self.is_full() || self.get_winner().is_some();
+        self.get_winner().is_some() || !self.rules().has_moves(self)
     }
 
     /// Returns true if dropping a piece into the given
     /// column is a valid move
     pub fn is_valid(&self, col: usize) -> bool {
-        col < COLS && self[(0, col)] == Cell::Empty
+        col < self.cols() && self[(0, col)] == Cell::Empty
     }
 
-    /// Returns all the possible columns that a piece
-    /// can be dropped into
-    pub fn get_valid(&self) -> Vec<usize> {
-        (0..COLS).filter(|&col| self.is_valid(col)).collect()
+    /// Returns true if the current player owns the piece
+    /// at the bottom of the given column, i.e. could pop it
+    /// (if the rules allow for popping)
+    pub fn can_pop(&self, col: usize) -> bool {
+        col < self.cols() && self[(self.rows() - 1, col)] == Cell::Player { player: self.turn }
+    }
+
+    /// Returns true if the given move can be played
+    /// by the current player
+    pub fn is_legal(&self, mv: Move) -> bool {
+        self.get_valid().contains(&mv)
+    }
+
+    /// Returns all the possible moves of the current player
+    /// according to the rules of the game, drops first
+    pub fn get_valid(&self) -> Vec<Move> {
+        self.rules().moves(self)
+    }
+
+    /// Play the given move for the current player
+    /// and update &mut self state
+    pub fn play(&mut self, mv: Move) {
+        self.rules().play(self, mv);
+    }
+
+    /// Create a new state with the given move played
+    /// and return it
+    pub fn played(&self, mv: Move) -> State {
+        let mut new_state = self.clone();
+        new_state.play(mv);
+        new_state
     }
 
     /// Drop a piece into the given column and update
     /// &mut self state
     pub fn drop(&mut self, col: usize, player: Player) {
-        for row in (0..ROWS).rev() {
+        for row in (0..self.rows()).rev() {
             if self[(row, col)] == Cell::Empty {
                 self[(row, col)] = Cell::Player { player };
+                self.turn = player.next(self.players);
+                self.last = Some((row, col));
                 break;
             }
         }
     }
 
+    /// Remove the piece at the bottom of the given column,
+    /// shifting the rest of the column down, and update
+    /// &mut self state
+    pub fn pop(&mut self, col: usize, player: Player) {
+        for row in (1..self.rows()).rev() {
+            self[(row, col)] = self[(row - 1, col)];
+        }
+
+        self[(0, col)] = Cell::Empty;
+        self.turn = player.next(self.players);
+    }
+
     /// Create a new state with the given column dropped
     /// and return it
     pub fn dropped(&self, col: usize, player: Player) -> State {
@@ -132,6 +413,35 @@ impl State {
     }
 }
 
+impl Default for State {
+    fn default() -> Self {
+        State::new()
+    }
+}
+
+impl PartialEq for State {
+    /// Compares the positions, regardless of the last move
+    fn eq(&self, other: &Self) -> bool {
+        (&self.data, self.win_length, self.variant, self.captured, self.players, self.cylinder, self.turn)
+            == (&other.data, other.win_length, other.variant, other.captured, other.players, other.cylinder, other.turn)
+    }
+}
+
+impl Eq for State {}
+
+impl Hash for State {
+    /// Hashes the position, regardless of the last move
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data.hash(state);
+        self.win_length.hash(state);
+        self.variant.hash(state);
+        self.captured.hash(state);
+        self.players.hash(state);
+        self.cylinder.hash(state);
+        self.turn.hash(state);
+    }
+}
+
 impl Index<(usize, usize)> for State {
     type Output = Cell;
 
@@ -148,6 +458,8 @@ impl IndexMut<(usize, usize)> for State {
     /// Note: Unusual syntax, `state[(row, col)]` instead
     ///       of `state[row][col]`
     fn index_mut(&mut self, index: (usize, usize)) -> &mut Self::Output {
+        // the cell may no longer hold the last dropped piece
+        self.last = None;
         &mut self.data[index.0][index.1]
     }
 }
@@ -160,7 +472,7 @@ impl fmt::Display for State {
             for cell in row.iter() {
                 s.push_str(&format!("{} ", cell));
             }
-            s.push_str("\n");
+            s.push('\n');
         }
         write!(f, "{}", s)
     }
@@ -169,26 +481,71 @@ impl fmt::Display for State {
 impl fmt::Debug for State {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut s = String::new();
-        let mut i = 0;
-        for row in self.data.iter() {
+        for (i, row) in self.data.iter().enumerate() {
             for cell in row.iter() {
                 let fmt_cell = match cell {
                     Cell::Empty => &format!("{}", i),
                     Cell::Player { player } => match player {
                         Player::Red => "R",
-                        Player::Yellow => "y"
+                        Player::Yellow => "y",
+                        Player::Green => "g"
                     }
                 };
 
                 s.push_str(fmt_cell);
             }
-            s.push_str("\n");
-            i += 1;
+            s.push('\n');
         }
         write!(f, "{}", s)
     }
 }
 
+/// A single move of a player
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Move {
+    Drop(usize),    // Drop a piece into the column
+    Pop(usize),     // Pop the own piece from the bottom of the column (PopOut)
+}
+
+impl Move {
+    /// Returns the column the move is played in
+    pub fn col(&self) -> usize {
+        match self {
+            Move::Drop(col) | Move::Pop(col) => *col
+        }
+    }
+}
+
+impl fmt::Display for Move {
+    /// Display the move in move notation, i.e. the
+    /// (1-based) column, prefixed with "p" for pops
+    /// e.g. "4" or "p4"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Move::Drop(col) => write!(f, "{}", col + 1),
+            Move::Pop(col) => write!(f, "p{}", col + 1)
+        }
+    }
+}
+
+impl FromStr for Move {
+    type Err = String;
+
+    /// Parse a move from move notation (see `Display`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (pop, col) = match s.strip_prefix(['p', 'P']) {
+            Some(col) => (true, col),
+            None => (false, s),
+        };
+
+        match col.parse::<usize>() {
+            Ok(col) if col > 0 => Ok(if pop { Move::Pop(col - 1) } else { Move::Drop(col - 1) }),
+            _ => Err(format!("invalid move '{}'", s)),
+        }
+    }
+}
+
 /// The state of any given cell (or "position") on the board
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
 pub enum Cell {
@@ -207,48 +564,78 @@ impl Cell {
 
     /// Returns true if the cell is empty
     pub fn is_empty(&self) -> bool {
-        match self {
-            Cell::Empty => true,
-            _ => false
-        }
+        matches!(self, Cell::Empty)
     }
 }
 
 impl fmt::Display for Cell {
     /// Display the cell as a single character for
     /// visualizing the board in a CLI
-    /// "·" or "R" or "y"
+    /// "·" or "R" or "y" or "g"
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Cell::Empty => write!(f, "·"),
             Cell::Player { player } => match player {
                 Player::Red => write!(f, "R"),
-                Player::Yellow => write!(f, "y")
+                Player::Yellow => write!(f, "y"),
+                Player::Green => write!(f, "g")
             }
         }
     }
 }
 
+/// The largest number of players a game may have
+pub const MAX_PLAYERS: usize = 3;
+
 /// An enum representing all possible players
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
 pub enum Player {
     Red,        // Player 1
-    Yellow      // Player 2
+    Yellow,     // Player 2
+    Green       // Player 3 (three-player games only)
 }
 
 impl Player {
-    /// Return the "other player" (i.e. the opponent)
-    pub fn other(&self) -> Player {
+    /// All players, in turn order
+    pub const ALL: [Player; MAX_PLAYERS] = [Player::Red, Player::Yellow, Player::Green];
+
+    /// Returns the index of the player (in turn order),
+    /// e.g. for indexing per-player arrays
+    pub fn index(&self) -> usize {
         match self {
-            Player::Red => Player::Yellow,
-            Player::Yellow => Player::Red
+            Player::Red => 0,
+            Player::Yellow => 1,
+            Player::Green => 2
         }
     }
 
-    /// Returns true if the given player is the opponent
+    /// Returns the player with the given index (in turn order)
+    pub fn from_index(index: usize) -> Player {
+        Player::ALL[index % MAX_PLAYERS]
+    }
+
+    /// Returns the player whose turn is next in
+    /// a game with the given number of players
+    pub fn next(&self, players: usize) -> Player {
+        Player::from_index((self.index() + 1) % players)
+    }
+
+    /// Returns the player whose turn was previous in
+    /// a game with the given number of players
+    pub fn previous(&self, players: usize) -> Player {
+        Player::from_index((self.index() + players - 1) % players)
+    }
+
+    /// Return the "other player" (i.e. the opponent)
+    /// in a two-player game
+    pub fn other(&self) -> Player {
+        self.next(2)
+    }
+
+    /// Returns true if the given player is an opponent
     /// of the &self player
     pub fn is_opponent(&self, other: Player) -> bool {
-        self.other() == other
+        *self != other
     }
 
     /// Returns true if the player is the maximizer
@@ -270,7 +657,8 @@ impl fmt::Display for Player {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Player::Red => write!(f, "P1 - Red"),
-            Player::Yellow => write!(f, "P2 - Yellow")
+            Player::Yellow => write!(f, "P2 - Yellow"),
+            Player::Green => write!(f, "P3 - Green")
         }
     }
 }
@@ -306,6 +694,27 @@ pub mod tests {
         );
     }
 
+    #[test]
+    /// Tests the `with_size` method of the State struct
+    /// for a few non-standard board sizes
+    fn test_with_size() {
+        for (rows, cols) in [(7, 6), (7, 8), (7, 9), (7, 7), (5, 6)] {
+            let state = State::with_size(rows, cols);
+
+            assert_eq!(
+                (state.rows(), state.cols()), (rows, cols),
+                "Expected a board of {} rows and {} columns, got {} and {}",
+                rows, cols, state.rows(), state.cols()
+            );
+
+            assert_eq!(
+                state.get_valid().len(), cols,
+                "Expected all {} columns to be valid, but they weren't",
+                cols
+            );
+        }
+    }
+
     #[test]
     /// Tests the `is_full` method of the State struct
     /// both positive and negative cases
@@ -519,4 +928,263 @@ pub mod tests {
             "Expected player 2 to be the second player, but it wasn't",
         );
     }
+
+    #[test]
+    /// Tests the `pop` method of the State struct by popping
+    /// the bottom piece of a column and checking if the rest
+    /// of the column was shifted down
+    fn test_pop() {
+        let mut state = State::new().with_variant(Variant::PopOut);
+
+        state.drop(0, Player::Red);
+        state.drop(0, Player::Yellow);
+
+        assert!(
+            state.can_pop(0) && !state.can_pop(1),
+            "Expected red to be able to pop column 0 only, but it wasn't",
+        );
+
+        state.play(Move::Pop(0));
+
+        assert_eq!(
+            state[(ROWS - 1, 0)], Cell::Player { player: Player::Yellow },
+            "Expected player 2 to be shifted to the last row of the column, but it wasn't",
+        );
+
+        assert_eq!(
+            state[(ROWS - 2, 0)], Cell::Empty,
+            "Expected the second to last row of the column to be empty, but it wasn't",
+        );
+
+        assert_eq!(
+            state.player(), Player::Yellow,
+            "Expected player 2 to be the next player after popping, but it wasn't",
+        );
+    }
+
+    #[test]
+    /// Tests that pops are only valid moves when
+    /// playing with the PopOut rules
+    fn test_get_valid_popout() {
+        let mut standard = State::new();
+        standard.drop(3, Player::Red);
+        standard.drop(2, Player::Yellow);
+
+        let popout = standard.clone().with_variant(Variant::PopOut);
+
+        assert!(
+            standard.get_valid().iter().all(|mv| matches!(mv, Move::Drop(_))),
+            "Expected only drops to be valid without PopOut, but they weren't",
+        );
+
+        assert_eq!(
+            popout.get_valid().len(), COLS + 1,
+            "Expected all drops and one pop to be valid, got {:?}",
+            popout.get_valid(),
+        );
+
+        assert!(
+            popout.get_valid().contains(&Move::Pop(3)),
+            "Expected popping column 3 to be valid, but it wasn't",
+        );
+    }
+
+    #[test]
+    /// Tests the move notation, i.e. the `Display`
+    /// and `FromStr` implementations of the Move enum
+    fn test_move_notation() {
+        for (mv, notation) in [(Move::Drop(0), "1"), (Move::Drop(9), "10"), (Move::Pop(3), "p4")] {
+            assert_eq!(
+                mv.to_string(), notation,
+                "Expected {:?} to be written as {}, but it wasn't",
+                mv, notation,
+            );
+
+            assert_eq!(
+                notation.parse::<Move>(), Ok(mv),
+                "Expected {} to be parsed as {:?}, but it wasn't",
+                notation, mv,
+            );
+        }
+
+        for invalid in ["", "0", "p", "x4", "-1"] {
+            assert!(
+                invalid.parse::<Move>().is_err(),
+                "Expected {:?} to be rejected, but it wasn't",
+                invalid,
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that repeating a position three times
+    /// draws the game (PopOut)
+    fn test_repetition() {
+        let mut board = Board::from_state(State::new().with_variant(Variant::PopOut));
+
+        for mv in [Move::Drop(0), Move::Drop(1)] {
+            board.play(mv);
+        }
+
+        // popping and re-dropping the bottom pieces
+        // results in the same position after 4 moves
+        for _ in 0 .. 2 {
+            assert!(
+                !board.is_over(),
+                "Expected the game not to be over yet, but it was",
+            );
+
+            for mv in [Move::Pop(0), Move::Pop(1), Move::Drop(0), Move::Drop(1)] {
+                assert!(
+                    board.play(mv),
+                    "Expected {} to be a legal move, but it wasn't",
+                    mv,
+                );
+            }
+        }
+
+        assert!(
+            board.is_repetition() && board.is_over(),
+            "Expected the game to be drawn by repetition, but it wasn't",
+        );
+
+        assert_eq!(
+            board.notation(), "1 2 p1 p2 1 2 p1 p2 1 2",
+            "Expected the moves to be recorded, got {}",
+            board.notation(),
+        );
+    }
+
+    #[test]
+    /// Tests that the canonical key of a position stays the
+    /// same, as keys are written to caches and tablebases
+    fn test_canonical_hash_stable() {
+        let mut state = State::new();
+        for col in [3, 3, 2] {
+            state.play(Move::Drop(col));
+        }
+
+        assert_eq!(
+            (State::new().canonical_hash(), state.canonical_hash()), (15147506093408291662, 10204784724327296321),
+            "Expected the keys of the fixed random numbers, got {:?}",
+            (State::new().canonical_hash(), state.canonical_hash())
+        );
+
+        let (mut wide, mut standard) = (State::with_size(6, 8), State::new());
+        wide.drop(0, Player::Red);
+        standard.drop(0, Player::Red);
+
+        assert!(
+            State::with_size(6, 8).canonical_hash() != State::new().canonical_hash() && wide.canonical_hash() != standard.canonical_hash(),
+            "Expected the keys of boards of different sizes to differ, but they didn't",
+        );
+    }
+
+    #[test]
+    /// Tests that mirrored positions (and rotated positions
+    /// on a cylinder) share the same canonical hash
+    fn test_canonical_hash() {
+        let mut state = State::new();
+        state.drop(0, Player::Red);
+        state.drop(1, Player::Yellow);
+
+        let mut mirrored = State::new();
+        mirrored.drop(COLS - 1, Player::Red);
+        mirrored.drop(COLS - 2, Player::Yellow);
+
+        let mut rotated = State::new();
+        rotated.drop(3, Player::Red);
+        rotated.drop(4, Player::Yellow);
+
+        assert_eq!(
+            state.canonical_hash(), mirrored.canonical_hash(),
+            "Expected mirrored positions to share a canonical hash, but they didn't",
+        );
+
+        assert_ne!(
+            state.canonical_hash(), rotated.canonical_hash(),
+            "Expected rotated positions on a flat board to differ, but they didn't",
+        );
+
+        let (state, rotated) = (state.with_cylinder(true), rotated.with_cylinder(true));
+
+        assert_eq!(
+            state.canonical_hash(), rotated.canonical_hash(),
+            "Expected rotated positions on a cylinder to share a canonical hash, but they didn't",
+        );
+    }
+
+    #[test]
+    /// Tests that the turn order cycles through
+    /// all players of a three-player game
+    fn test_three_player_turns() {
+        let mut state = State::with_size(7, 9).with_players(3);
+        let expected = [Player::Red, Player::Yellow, Player::Green, Player::Red];
+
+        for (col, player) in expected.iter().enumerate() {
+            assert_eq!(
+                state.player(), *player,
+                "Expected {} to be the current player, but it wasn't",
+                player,
+            );
+
+            state.play(Move::Drop(col));
+        }
+
+        assert_eq!(
+            Player::Red.previous(3), Player::Green,
+            "Expected player 3 to be the previous player of player 1, but it wasn't",
+        );
+
+        assert_eq!(
+            Player::Yellow.next(2), Player::Red,
+            "Expected player 1 to be the next player of player 2 in a two-player game, but it wasn't",
+        );
+    }
+
+    #[test]
+    /// Tests that the game is decided by the last dropped piece,
+    /// and by scanning the board once the board was edited
+    fn test_is_terminal_last_move() {
+        let mut state = State::new();
+
+        for col in [0, 6, 1, 6, 2, 6] {
+            state.play(Move::Drop(col));
+        }
+
+        assert!(
+            !state.is_terminal() && state.last == Some((3, 6)),
+            "Expected an ongoing game after a drop into column 6, got {:?}",
+            state.last
+        );
+
+        state.play(Move::Drop(3));
+
+        assert_eq!(
+            (state.get_winner(), state.is_terminal()), (Some(Player::Red), true),
+            "Expected red to win with the last drop, got {:?}",
+            state.get_winner()
+        );
+
+        let mut edited = State::new();
+        for col in 0 .. 4 {
+            edited[(5, col)] = Cell::Player { player: Player::Yellow };
+        }
+
+        assert_eq!(
+            (edited.last, edited.get_winner()), (None, Some(Player::Yellow)),
+            "Expected the edited board to be scanned for a winner, got {:?}",
+            edited.get_winner()
+        );
+
+        for variant in Variant::ALL {
+            let state = State::new().with_variant(variant);
+
+            assert_eq!(
+                state.rules().has_moves(&state), !state.get_valid().is_empty(),
+                "Expected has_moves to agree with the legal moves of {}",
+                variant
+            );
+        }
+    }
 }
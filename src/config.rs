@@ -0,0 +1,411 @@
+use crate::{board::{State, COLS, MAX_DIMENSION, MAX_PLAYERS, MIN_DIMENSION, ROWS}, detect::{MAX_WIN_LENGTH, MIN_WIN_LENGTH, WIN_LENGTH}, eval::{difficulty::Difficulty, engine::EngineKind, evaluator::{Evaluator, EvaluatorKind}, minimax::MAX_THREADS, params::Params, tablebase::Tablebase}, rules::Variant};
+
+/// The default number of rows of a three-player game
+pub const THREE_PLAYER_ROWS: usize = 7;
+
+/// The default number of columns of a three-player game
+pub const THREE_PLAYER_COLS: usize = 9;
+
+/// The runtime configuration of the game, usually
+/// parsed from the command line arguments
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub rows: usize,
+    pub cols: usize,
+    pub win_length: usize,
+    pub variant: Variant,
+    pub cylinder: bool,
+    pub players: usize,
+    pub evaluator: EvaluatorKind,
+    pub threads: usize,
+    pub difficulty: Difficulty,
+    pub engine: EngineKind,
+
+    /// the parameter file of the evaluation, if any (see `Params`)
+    pub params: Option<String>,
+
+    /// the weights file of the network evaluator, if any (see `Network`)
+    #[cfg(feature = "nn")]
+    pub network: Option<String>,
+
+    /// the tablebase file probed by the search, if any (see `Tablebase`)
+    pub tablebase: Option<String>,
+
+    /// the cache file of the transposition table, if any, loaded at
+    /// startup and written on exit (see `TranspositionTable::save`)
+    pub cache: Option<String>,
+
+    /// whether the engine searches on the human's turn (see `Engine::ponder`)
+    pub ponder: bool,
+}
+
+impl Config {
+    /// Parses the configuration from the given command
+    /// line arguments (excluding the program name)
+    ///
+    /// Supported arguments:
+    ///     --size <cols>x<rows>    board size, e.g. "7x6" (default)
+    ///     --connect <n>           pieces in a row needed to win (3 - 6)
+    ///     --variant <name>        rule set: standard (default), popout, five, pop10
+    ///     --cylinder              wrap the board around horizontally
+    ///     --players <n>           number of players (2 or 3), three players
+    ///                             play on a larger (9x7) board by default
+    ///     --eval <name>           engine evaluation: weights, window (default),
+    ///                             threat, zero
+    ///     --threads <n>           number of engine threads (default: one
+    ///                             per CPU core)
+    ///     --difficulty <name>     initial engine strength: beginner, easy,
+    ///                             medium, hard (default), expert, perfect
+    ///     --engine <name>         engine algorithm: negamax (default), mcts
+    ///     --params <file>         tuned parameters of the weights evaluation (see `tune`)
+    ///     --network <file>        weights of the network evaluator (feature "nn")
+    ///     --tablebase <file>      endgame tablebase probed by the search
+    ///     --cache <file>          analysis cache kept across runs
+    ///     --no-ponder             don't search on the human's turn
+    pub fn from_args<I>(args: I) -> Result<Config, String>
+    where
+        I: IntoIterator<Item = String>
+    {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+        let mut size = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--size" => {
+                    let value = args.next().ok_or("missing value for --size")?;
+                    size = Some(parse_size(&value)?);
+                }
+                "--connect" => {
+                    let value = args.next().ok_or("missing value for --connect")?;
+                    config.win_length = parse_win_length(&value)?;
+                }
+                "--variant" => {
+                    let value = args.next().ok_or("missing value for --variant")?;
+                    config.variant = value.parse()?;
+                }
+                "--cylinder" => config.cylinder = true,
+                "--players" => {
+                    let value = args.next().ok_or("missing value for --players")?;
+                    config.players = parse_players(&value)?;
+                }
+                "--eval" => {
+                    let value = args.next().ok_or("missing value for --eval")?;
+                    config.evaluator = value.parse()?;
+                }
+                "--threads" => {
+                    let value = args.next().ok_or("missing value for --threads")?;
+                    config.threads = parse_threads(&value)?;
+                }
+                "--difficulty" => {
+                    let value = args.next().ok_or("missing value for --difficulty")?;
+                    config.difficulty = value.parse()?;
+                }
+                "--engine" => {
+                    let value = args.next().ok_or("missing value for --engine")?;
+                    config.engine = value.parse()?;
+                }
+                "--params" => {
+                    let value = args.next().ok_or("missing value for --params")?;
+                    config.params = Some(value);
+                }
+                #[cfg(feature = "nn")]
+                "--network" => {
+                    let value = args.next().ok_or("missing value for --network")?;
+                    config.network = Some(value);
+                }
+                "--tablebase" => {
+                    let value = args.next().ok_or("missing value for --tablebase")?;
+                    config.tablebase = Some(value);
+                }
+                "--cache" => {
+                    let value = args.next().ok_or("missing value for --cache")?;
+                    config.cache = Some(value);
+                }
+                "--no-ponder" => config.ponder = false,
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+
+        (config.cols, config.rows) = match size {
+            Some(size) => size,
+            None if config.players > 2 => (THREE_PLAYER_COLS, THREE_PLAYER_ROWS),
+            None => (COLS, ROWS),
+        };
+
+        if config.win_length > config.rows.max(config.cols) {
+            return Err(format!(
+                "cannot connect {} on a {}x{} board",
+                config.win_length, config.cols, config.rows
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the initial state of a game played with this
+    /// configuration. Note that some variants (e.g. "5-in-a-Row")
+    /// come with their own board size and win length.
+    pub fn state(&self) -> State {
+        State::with_size(self.rows, self.cols)
+            .with_win_length(self.win_length)
+            .with_variant(self.variant)
+            .with_cylinder(self.cylinder)
+            .with_players(self.players)
+    }
+
+    /// Returns the parameters of the parameter file, if any
+    pub fn load_params(&self) -> Result<Option<Params>, String> {
+        self.params.as_deref().map(Params::load).transpose()
+    }
+
+    /// Returns the selected evaluator, using the parameters of the
+    /// parameter file, if any. Warns if the evaluator ignores them.
+    pub fn load_evaluator(&self) -> Result<&'static dyn Evaluator, String> {
+        let params = self.load_params()?;
+        if params.is_some() && !self.evaluator.takes_params() {
+            eprintln!("warning: --params only affects the weights evaluator, not {} (see --eval)", self.evaluator);
+        }
+
+        Ok(self.evaluator.with_params(params))
+    }
+
+    /// Installs the network of the weights file and the tablebase,
+    /// if any, for the rest of the program (see `Network` and
+    /// `Tablebase`)
+    pub fn install(&self) -> Result<(), String> {
+        #[cfg(feature = "nn")]
+        if let Some(path) = &self.network {
+            crate::eval::nn::Network::load(path)?.install()?;
+        }
+
+        if let Some(path) = &self.tablebase {
+            Tablebase::load(path)?.install()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            rows: ROWS,
+            cols: COLS,
+            win_length: WIN_LENGTH,
+            variant: Variant::Standard,
+            cylinder: false,
+            players: 2,
+            evaluator: EvaluatorKind::default(),
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get().min(MAX_THREADS)),
+            difficulty: Difficulty::default(),
+            engine: EngineKind::default(),
+            params: None,
+            #[cfg(feature = "nn")]
+            network: None,
+            tablebase: None,
+            cache: None,
+            ponder: true,
+        }
+    }
+}
+
+/// Parses a board size in the form "<cols>x<rows>"
+/// and returns it as a (cols, rows) tuple
+pub fn parse_size(value: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("invalid board size '{}', expected e.g. '7x6'", value);
+
+    let (cols, rows) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let cols: usize = cols.trim().parse().map_err(|_| invalid())?;
+    let rows: usize = rows.trim().parse().map_err(|_| invalid())?;
+
+    for dimension in [cols, rows] {
+        if !(MIN_DIMENSION ..= MAX_DIMENSION).contains(&dimension) {
+            return Err(format!(
+                "invalid board size '{}', dimensions must be between {} and {}",
+                value, MIN_DIMENSION, MAX_DIMENSION
+            ));
+        }
+    }
+
+    Ok((cols, rows))
+}
+
+/// Parses the number of pieces in a row needed to win
+pub fn parse_win_length(value: &str) -> Result<usize, String> {
+    match value.trim().parse() {
+        Ok(n) if (MIN_WIN_LENGTH ..= MAX_WIN_LENGTH).contains(&n) => Ok(n),
+        _ => Err(format!(
+            "invalid win length '{}', expected a number between {} and {}",
+            value, MIN_WIN_LENGTH, MAX_WIN_LENGTH
+        )),
+    }
+}
+
+/// Parses the number of players
+pub fn parse_players(value: &str) -> Result<usize, String> {
+    match value.trim().parse() {
+        Ok(n) if (2 ..= MAX_PLAYERS).contains(&n) => Ok(n),
+        _ => Err(format!(
+            "invalid number of players '{}', expected a number between 2 and {}",
+            value, MAX_PLAYERS
+        )),
+    }
+}
+
+/// Parses the number of engine threads
+pub fn parse_threads(value: &str) -> Result<usize, String> {
+    match value.trim().parse() {
+        Ok(n) if (1 ..= MAX_THREADS).contains(&n) => Ok(n),
+        _ => Err(format!(
+            "invalid number of threads '{}', expected a number between 1 and {}",
+            value, MAX_THREADS
+        )),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_args_default() {
+        let config = Config::from_args(args(&[])).unwrap();
+
+        assert_eq!(
+            config, Config::default(),
+            "Expected no arguments to result in the default config, got {:?}",
+            config
+        );
+    }
+
+    #[test]
+    fn test_from_args_size() {
+        let config = Config::from_args(args(&["--size", "9x7"])).unwrap();
+
+        assert_eq!(
+            (config.cols, config.rows), (9, 7),
+            "Expected a 9x7 board, got {}x{}",
+            config.cols, config.rows
+        );
+    }
+
+    #[test]
+    fn test_from_args_connect() {
+        let config = Config::from_args(args(&["--size", "6x5", "--connect", "3"])).unwrap();
+        let state = config.state();
+
+        assert_eq!(
+            state.win_length, 3,
+            "Expected a win length of 3, got {}",
+            state.win_length
+        );
+    }
+
+    #[test]
+    fn test_from_args_variant() {
+        let config = Config::from_args(args(&["--variant", "five"])).unwrap();
+        let state = config.state();
+
+        assert_eq!(
+            (state.variant, state.cols(), state.rows()), (Variant::FiveInARow, 9, 6),
+            "Expected a 9x6 board of 5-in-a-Row, got {:?} on {}x{}",
+            state.variant, state.cols(), state.rows()
+        );
+    }
+
+    #[test]
+    fn test_from_args_players() {
+        let config = Config::from_args(args(&["--players", "3"])).unwrap();
+
+        assert_eq!(
+            (config.players, config.cols, config.rows), (3, THREE_PLAYER_COLS, THREE_PLAYER_ROWS),
+            "Expected three players on a larger board, got {:?}",
+            config
+        );
+
+        let config = Config::from_args(args(&["--players", "3", "--size", "7x6"])).unwrap();
+
+        assert_eq!(
+            (config.cols, config.rows), (7, 6),
+            "Expected an explicit size to be kept, got {}x{}",
+            config.cols, config.rows
+        );
+    }
+
+    #[test]
+    fn test_from_args_eval() {
+        let config = Config::from_args(args(&["--eval", "zero"])).unwrap();
+
+        assert_eq!(
+            config.evaluator, EvaluatorKind::Zero,
+            "Expected the zero evaluator, got {:?}",
+            config.evaluator
+        );
+    }
+
+    #[test]
+    fn test_from_args_threads() {
+        let config = Config::from_args(args(&["--threads", "4"])).unwrap();
+
+        assert_eq!(
+            config.threads, 4,
+            "Expected 4 threads, got {}",
+            config.threads
+        );
+    }
+
+    #[test]
+    fn test_from_args_difficulty() {
+        let config = Config::from_args(args(&["--difficulty", "easy"])).unwrap();
+
+        assert_eq!(
+            config.difficulty, Difficulty::Easy,
+            "Expected the easy difficulty, got {:?}",
+            config.difficulty
+        );
+    }
+
+    #[test]
+    fn test_from_args_engine() {
+        let config = Config::from_args(args(&["--engine", "mcts"])).unwrap();
+
+        assert_eq!(
+            config.engine, EngineKind::Mcts,
+            "Expected the MCTS engine, got {:?}",
+            config.engine
+        );
+    }
+
+    #[test]
+    fn test_from_args_ponder() {
+        let (default, disabled) = (Config::from_args(args(&[])).unwrap(), Config::from_args(args(&["--no-ponder"])).unwrap());
+
+        assert_eq!(
+            (default.ponder, disabled.ponder), (true, false),
+            "Expected pondering to be disabled by --no-ponder only, got {:?}",
+            (default.ponder, disabled.ponder)
+        );
+    }
+
+    #[test]
+    fn test_from_args_invalid() {
+        let invalid_args = [
+            &["--size"][..], &["--size", "7"], &["--size", "3x6"], &["--size", "7x11"],
+            &["--connect", "2"], &["--connect", "7"], &["--size", "4x4", "--connect", "5"],
+            &["--variant", "bogus"], &["--players", "1"], &["--players", "4"], &["--eval", "bogus"], &["--threads", "0"], &["--difficulty", "bogus"], &["--engine", "bogus"], &["--params"], &["--tablebase"], &["--cache"], &["--bogus"],
+        ];
+
+        for invalid in invalid_args {
+            assert!(
+                Config::from_args(args(invalid)).is_err(),
+                "Expected {:?} to be rejected, but it wasn't",
+                invalid
+            );
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::board::{Cell, Player, State};
+
+/// A bitboard of one player's pieces. Cells are stored column
+/// by column, bottom to top, with one spare (always empty) bit
+/// on top of every column. The spare bits keep shifted lines
+/// from continuing from the top of one column into the bottom
+/// of the next one. A board of at most 10x10 cells (plus spare
+/// bits) fits into 128 bits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Hash)]
+pub struct Bitboard(pub u128);
+
+impl Bitboard {
+    /// Returns the bitboard of the given player's pieces
+    pub fn from_state(state: &State, player: Player) -> Bitboard {
+        let rows = state.rows();
+        let mut bits = 0;
+
+        for (row, cells) in state.data.iter().enumerate() {
+            for (col, cell) in cells.iter().enumerate() {
+                if *cell == (Cell::Player { player }) {
+                    bits |= 1 << Bitboard::index(rows, row, col);
+                }
+            }
+        }
+
+        Bitboard(bits)
+    }
+
+    /// Returns the bit index of the given cell
+    /// on a board with the given number of rows
+    pub fn index(rows: usize, row: usize, col: usize) -> usize {
+        col * (rows + 1) + (rows - 1 - row)
+    }
+
+    /// Returns true if the bitboard contains (at least)
+    /// `win_length` pieces in a row, for a board with the
+    /// given number of rows (not supporting cylinders)
+    pub fn has_line(&self, rows: usize, win_length: usize) -> bool {
+        let height = rows + 1;
+
+        // vertical, horizontal and both diagonals
+        for shift in [1, height, height + 1, height - 1] {
+            let mut line = self.0;
+            for k in 1 .. win_length {
+                line &= self.0 >> (shift * k);
+            }
+
+            if line != 0 {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests that lines in all four directions are found
+    fn test_has_line() {
+        let lines = [
+            [(5, 0), (5, 1), (5, 2), (5, 3)],
+            [(2, 6), (3, 6), (4, 6), (5, 6)],
+            [(2, 0), (3, 1), (4, 2), (5, 3)],
+            [(2, 3), (3, 2), (4, 1), (5, 0)],
+        ];
+
+        for line in lines {
+            let mut state = State::new();
+            for (row, col) in line {
+                state[(row, col)] = Cell::Player { player: Player::Red };
+            }
+
+            let bitboard = Bitboard::from_state(&state, Player::Red);
+
+            assert!(
+                bitboard.has_line(state.rows(), 4),
+                "Expected a line to be found at {:?}, but it wasn't",
+                line
+            );
+
+            assert!(
+                !bitboard.has_line(state.rows(), 5),
+                "Expected no line of five to be found at {:?}, but it was",
+                line
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that pieces at the top of one column and the
+    /// bottom of the next one don't form a line
+    fn test_has_line_column_boundary() {
+        let mut state = State::new();
+
+        state[(0, 0)] = Cell::Player { player: Player::Yellow };
+        state[(1, 0)] = Cell::Player { player: Player::Yellow };
+        state[(5, 1)] = Cell::Player { player: Player::Yellow };
+        state[(4, 1)] = Cell::Player { player: Player::Yellow };
+
+        let bitboard = Bitboard::from_state(&state, Player::Yellow);
+
+        assert!(
+            !bitboard.has_line(state.rows(), 4),
+            "Expected no line across the column boundary, but there was",
+        );
+    }
+}
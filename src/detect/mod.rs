@@ -0,0 +1,738 @@
+use crate::board::{Cell, Player, State};
+
+use bitboard::Bitboard;
+
+pub mod bitboard;
+pub mod threats;
+
+/// All directions that we can check for a win,
+/// in form of (dr, dc) tuples.
+pub const DIRECTIONS: [(i32, i32); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];  // right, down, down-right, down-left
+
+/// The number of pieces in a row needed to win
+/// in standard connect four
+pub const WIN_LENGTH: usize = 4;
+
+/// The smallest supported number of pieces in a row needed to win
+pub const MIN_WIN_LENGTH: usize = 3;
+
+/// The largest supported number of pieces in a row needed to win
+pub const MAX_WIN_LENGTH: usize = 6;
+
+pub type Direction = (i32, i32);
+pub type Sequence = Vec<(usize, usize)>;
+
+pub fn sequences_all(state: &State, player: Player) -> Vec<Sequence> {
+    let mut sequences = Vec::new();
+    
+    for row in 0 .. state.rows() {
+        for col in 0 .. state.cols() {
+            let seqs = sequences_from_position(state, player, row, col);
+            sequences.extend(seqs);
+        }
+    }
+
+    sequences.sort();
+    sequences.dedup();
+
+    sequences
+}
+
+/// Returns all sequences (of at least two pieces) of the given
+/// player that contain the given position. The cells of each
+/// sequence are ordered along the line, which (apart from lines
+/// wrapping around a cylinder) is the same as sorting them.
+pub fn sequences_from_position(state: &State, player: Player, row: usize, col: usize) -> Vec<Sequence> {
+    let mut sequences = Vec::new();
+
+    if let Cell::Player { player: p } = state[(row, col)] && p == player {
+        for direction in DIRECTIONS.iter() {
+            let forward = sequence_in_direction(state, player, row, col, (direction.0, direction.1));
+
+            // a full row on a cylinder has neither start nor end
+            if state.cylinder && direction.0 == 0 && forward.len() == state.cols() {
+                sequences.push((0 .. state.cols()).map(|c| (row, c)).collect());
+                continue;
+            }
+
+            let backward = sequence_in_direction(state, player, row, col, (-direction.0, -direction.1));
+
+            let mut seq: Sequence = backward.into_iter().skip(1).rev().collect();
+            seq.extend(forward);
+
+            sequences.push(seq);
+        }
+    }
+
+    let mut sequences = sequences
+        .into_iter()
+        .filter(|seq| seq.len() > 1)
+        .collect::<Vec<_>>();
+
+    sequences.sort();
+    sequences.dedup();
+    sequences
+}
+
+/// Returns the sequence of the given player's pieces starting at
+/// the given position and following the given direction. On a
+/// cylinder, the sequence continues across the left and right edge.
+pub fn sequence_in_direction(state: &State, player: Player, row: usize, col: usize, direction: Direction) -> Sequence {
+    let mut sequence = Vec::new();
+    
+    let mut r = row as i32;
+    let mut c = col as i32;
+
+    let (dr, dc) = direction;
+    let (rows, cols) = (state.rows() as i32, state.cols() as i32);
+
+    while r >= 0 && r < rows {
+        if state.cylinder {
+            c = c.rem_euclid(cols);
+        } else if c < 0 || c >= cols {
+            break;
+        }
+
+        // went all the way around the cylinder
+        if !sequence.is_empty() && (r as usize, c as usize) == (row, col) {
+            break;
+        }
+
+        match state[(r as usize, c as usize)] {
+            Cell::Player { player: p } if p == player => sequence.push((r as usize, c as usize)),
+            _ => break,
+        }
+
+        r += dr;
+        c += dc;
+    }
+
+    sequence
+}
+
+impl State {
+    /// Returns the winner of the game according to
+    /// the rules of the game's variant, if any
+    pub fn get_winner(&self) -> Option<Player> {
+        self.rules().winner(self)
+    }
+
+    /// Returns true if the given player has (at least)
+    /// `win_length` pieces in a row. Uses a bitboard check,
+    /// or (on a cylinder) checks the lines through every piece,
+    /// neither of which allocates.
+    pub fn has_connected(&self, player: Player) -> bool {
+        if !self.cylinder {
+            return Bitboard::from_state(self, player).has_line(self.rows(), self.win_length);
+        }
+
+        (0 .. self.rows()).any(|row| {
+            (0 .. self.cols()).any(|col| {
+                self[(row, col)] == Cell::Player { player } && self.connects_at(row, col)
+            })
+        })
+    }
+
+    /// Returns true if the piece at the given position is part
+    /// of (at least) `win_length` pieces in a row, i.e. whether
+    /// the last move won the game if it placed this piece
+    pub fn connects_at(&self, row: usize, col: usize) -> bool {
+        let Cell::Player { player } = self[(row, col)] else {
+            return false;
+        };
+
+        self.completes_line(player, row, col)
+    }
+
+    /// Returns true if a piece of the given player at the given
+    /// position would be part of (at least) `win_length` pieces
+    /// in a row, regardless of what the position currently holds
+    pub fn completes_line(&self, player: Player, row: usize, col: usize) -> bool {
+        DIRECTIONS.iter().any(|&(dr, dc)| {
+            let len = 1
+                + self.count_in_direction(player, row, col, (dr, dc))
+                + self.count_in_direction(player, row, col, (-dr, -dc));
+
+            len >= self.win_length
+        })
+    }
+
+    /// Returns true if the current player would win by dropping
+    /// a piece into the given column. Only the lines through the
+    /// cell the piece would land on are checked, so this takes
+    /// constant time and does not allocate.
+    pub fn wins_after(&self, col: usize) -> bool {
+        let Some(row) = (0 .. self.rows()).rev().find(|&row| self[(row, col)].is_empty()) else {
+            return false;
+        };
+
+        self.completes_line(self.player(), row, col)
+    }
+
+    /// Counts the given player's pieces in a row, starting
+    /// next to (and excluding) the given position and following
+    /// the given direction, without allocating
+    fn count_in_direction(&self, player: Player, row: usize, col: usize, direction: Direction) -> usize {
+        let (dr, dc) = direction;
+        let (rows, cols) = (self.rows() as i32, self.cols() as i32);
+
+        let mut r = row as i32 + dr;
+        let mut c = col as i32 + dc;
+        let mut count = 0;
+
+        // a row on a cylinder has no end, so horizontal steps stop
+        // after a full row besides the start (any other direction
+        // leaves the board at the top or bottom)
+        while r >= 0 && r < rows && (dr != 0 || count < self.cols() - 1) {
+            if self.cylinder {
+                c = c.rem_euclid(cols);
+            } else if c < 0 || c >= cols {
+                break;
+            }
+
+            if self[(r as usize, c as usize)] != (Cell::Player { player }) {
+                break;
+            }
+
+            count += 1;
+            r += dr;
+            c += dc;
+        }
+
+        count
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::board::{Move, Player, State, COLS};
+    use super::*;
+
+    #[test]
+    /// Tests direction (dx = 1, dy = 0) or towards the right
+    fn test_sequence_in_direction_1() {
+        let mut state = State::new();
+        let fields = vec![(5, 0), (5, 1), (5, 2), (5, 3)];
+
+        state[(5, 0)] = Cell::Player { player: Player::Red };
+        state[(5, 1)] = Cell::Player { player: Player::Red };
+        state[(5, 2)] = Cell::Player { player: Player::Red };
+        state[(5, 3)] = Cell::Player { player: Player::Red };
+
+        let seq = sequence_in_direction(&state, Player::Red, 5, 0, DIRECTIONS[0]);
+        
+        assert_eq!(
+            seq.len(), 4,
+            "Expected sequence of length 4, got {}",
+            seq.len()
+        );
+
+        assert_eq!(
+            seq, fields,
+            "Expected sequence {:?}, got {:?}",
+            fields,
+            seq
+        );
+    }
+
+    #[test]
+    /// Tests direction (0, 1) or downwards
+    fn test_sequence_in_direction_2() {
+        let mut state = State::new();
+        let fields = vec![(2, 0), (3, 0), (4, 0), (5, 0)];
+
+        state[(5, 0)] = Cell::Player { player: Player::Red };
+        state[(4, 0)] = Cell::Player { player: Player::Red };
+        state[(3, 0)] = Cell::Player { player: Player::Red };
+        state[(2, 0)] = Cell::Player { player: Player::Red };
+
+        let seq = sequence_in_direction(&state, Player::Red, 2, 0, DIRECTIONS[1]);
+        
+        assert_eq!(
+            seq.len(), 4,
+            "Expected sequence of length 4, got {}",
+            seq.len()
+        );
+
+        assert_eq!(
+            seq, fields,
+            "Expected sequence {:?}, got {:?}",
+            fields,
+            seq
+        );
+    }
+
+    #[test]
+    /// Tests direction (1, -1) or diagonally towards the bottom right
+    fn test_sequence_in_direction_3() {
+        let mut state = State::new();
+        let fields = vec![(2, 0), (3, 1), (4, 2), (5, 3)];
+
+        state[(2, 0)] = Cell::Player { player: Player::Red };
+        state[(3, 1)] = Cell::Player { player: Player::Red };
+        state[(4, 2)] = Cell::Player { player: Player::Red };
+        state[(5, 3)] = Cell::Player { player: Player::Red };
+
+        println!("{}", state);
+
+        let seq = sequence_in_direction(&state, Player::Red, 2, 0, DIRECTIONS[2]);
+
+        assert_eq!(
+            seq.len(), 4,
+            "Expected sequence of length 4, got {}",
+            seq.len()
+        );
+
+        assert_eq!(
+            seq, fields,
+            "Expected sequence {:?}, got {:?}",
+            fields,
+            seq
+        );
+    }
+
+    #[test]
+    /// Tests direction (1, -1) or towards the bottom left
+    fn test_sequence_in_direction_4() {
+        let mut state = State::new();
+        let fields = vec![(2, 3), (3, 2), (4, 1), (5, 0)];
+
+        state[(2, 3)] = Cell::Player { player: Player::Red };
+        state[(3, 2)] = Cell::Player { player: Player::Red };
+        state[(4, 1)] = Cell::Player { player: Player::Red };
+        state[(5, 0)] = Cell::Player { player: Player::Red };
+
+        println!("{}", state);
+
+        let seq = sequence_in_direction(&state, Player::Red, 2, 3, DIRECTIONS[3]);
+
+        assert_eq!(
+            seq.len(), 4,
+            "Expected sequence of length 4, got {}",
+            seq.len()
+        );
+
+        assert_eq!(
+            seq, fields,
+            "Expected sequence {:?}, got {:?}",
+            fields,
+            seq
+        );
+    }
+
+    #[test]
+    fn test_sequence_from_position_1() {
+        let mut state = State::new();
+        let fields = vec![(2, 3), (3, 2), (4, 1), (5, 0)];
+
+        state[(2, 3)] = Cell::Player { player: Player::Red };
+        state[(3, 2)] = Cell::Player { player: Player::Red };
+        state[(4, 1)] = Cell::Player { player: Player::Red };
+        state[(5, 0)] = Cell::Player { player: Player::Red };
+    
+        let seqs1 = sequences_from_position(&state, Player::Red, 2, 3);
+        let seqs2 = sequences_from_position(&state, Player::Red, 5, 0);
+
+        println!("{:?}", seqs1);
+        println!("{:?}", seqs2);
+
+        assert_eq!(
+            seqs1.len(), 1,
+            "Expected 2 sequences, got {}",
+            seqs1.len()
+        );
+
+        assert_eq!(
+            seqs2.len(), 1,
+            "Expected 2 sequences, got {}",
+            seqs2.len()
+        );
+
+        assert_eq!(
+            seqs1[0], fields,
+            "Expected sequence {:?}, got {:?}",
+            fields,
+            seqs1[0]
+        );
+
+        assert_eq!(
+            seqs2[0], fields,
+            "Expected {:?}, got {:?}",
+            fields,
+            seqs2[0]
+        );
+    }
+
+    #[test]
+    fn test_sequence_from_position_2() {
+        let mut state = State::new();
+        let mut fields = vec![(5, 0), (4, 0), (3, 0), (2, 0)];
+        fields.sort();
+
+        state[(2, 0)] = Cell::Player { player: Player::Red };
+        state[(3, 0)] = Cell::Player { player: Player::Red };
+        state[(4, 0)] = Cell::Player { player: Player::Red };
+        state[(5, 0)] = Cell::Player { player: Player::Red };
+    
+        let seqs1 = sequences_from_position(&state, Player::Red, 5, 0);
+        let seqs2 = sequences_from_position(&state, Player::Red, 2, 0);
+
+        println!("{:?}", seqs1);
+        println!("{:?}", seqs2);
+
+        assert_eq!(
+            seqs1.len(), 1,
+            "Expected 2 sequences, got {}",
+            seqs1.len()
+        );
+
+        assert_eq!(
+            seqs2.len(), 1,
+            "Expected 2 sequences, got {}",
+            seqs2.len()
+        );
+
+        assert_eq!(
+            seqs1[0], fields,
+            "Expected sequence {:?}, got {:?}",
+            fields,
+            seqs1[0]
+        );
+
+        assert_eq!(
+            seqs2[0], fields,
+            "Expected {:?}, got {:?}",
+            fields,
+            seqs2[0]
+        );
+    }
+
+    #[test]
+    fn test_sequences_all() {
+        let mut state = State::new();
+        let fields = vec![(2, 3), (3, 2), (4, 1), (5, 0)];
+
+        state[(2, 3)] = Cell::Player { player: Player::Red };
+        state[(3, 2)] = Cell::Player { player: Player::Red };
+        state[(4, 1)] = Cell::Player { player: Player::Red };
+        state[(5, 0)] = Cell::Player { player: Player::Red };
+
+        let seqs = sequences_all(&state, Player::Red);
+        println!("{:#?}", seqs);
+
+        assert_eq!(
+            seqs.len(), 1,
+            "Expected 1 sequence, got {}",
+            seqs.len()
+        );
+
+        assert_eq!(
+            seqs[0], fields,
+            "Expected sequence {:?}, got {:?}",
+            fields,
+            seqs[0]
+        );
+    }
+
+    #[test]
+    fn test_get_winner_1() {
+        let mut state = State::new();
+
+        state[(2, 3)] = Cell::Player { player: Player::Red };
+        state[(3, 2)] = Cell::Player { player: Player::Red };
+        state[(4, 1)] = Cell::Player { player: Player::Red };
+        state[(5, 0)] = Cell::Player { player: Player::Red };
+
+        let winner = state.get_winner();
+
+        assert_eq!(
+            winner, Some(Player::Red),
+            "Expected winner to be Red, got {:?}",
+            winner
+        );
+    }
+
+    #[test]
+    fn test_get_winner_2() {
+        let mut state = State::new();
+
+        state[(2, 3)] = Cell::Player { player: Player::Yellow };
+        state[(3, 2)] = Cell::Player { player: Player::Yellow };
+        state[(4, 1)] = Cell::Player { player: Player::Yellow };
+        state[(5, 0)] = Cell::Player { player: Player::Yellow };
+
+        let winner = state.get_winner();
+
+        assert_eq!(
+            winner, Some(Player::Yellow),
+            "Expected winner to be Yellow, got {:?}",
+            winner
+        );
+    }
+
+    #[test]
+    fn test_get_winner_3() {
+        let mut state = State::new();
+
+        state[(2, 3)] = Cell::Player { player: Player::Red };
+        state[(3, 2)] = Cell::Player { player: Player::Red };
+        state[(4, 1)] = Cell::Player { player: Player::Red };
+
+        state[(2, 2)] = Cell::Player { player: Player::Yellow };
+        state[(3, 1)] = Cell::Player { player: Player::Yellow };
+        state[(4, 0)] = Cell::Player { player: Player::Yellow };
+
+        let winner = state.get_winner();
+
+        assert_eq!(
+            winner, None,
+            "Expected no winner, got {:?}",
+            winner
+        );
+    }
+
+    #[test]
+    /// Tests that three in a row wins a game of Connect-3
+    fn test_get_winner_connect_3() {
+        let mut state = State::with_size(5, 6).with_win_length(3);
+
+        state[(4, 1)] = Cell::Player { player: Player::Yellow };
+        state[(4, 2)] = Cell::Player { player: Player::Yellow };
+
+        assert_eq!(
+            state.get_winner(), None,
+            "Expected no winner with two in a row, got {:?}",
+            state.get_winner()
+        );
+
+        state[(4, 3)] = Cell::Player { player: Player::Yellow };
+
+        assert_eq!(
+            state.get_winner(), Some(Player::Yellow),
+            "Expected winner to be Yellow, got {:?}",
+            state.get_winner()
+        );
+    }
+
+    #[test]
+    /// Tests that four in a row does not win a game of Connect-5
+    fn test_get_winner_connect_5() {
+        let mut state = State::with_size(7, 9).with_win_length(5);
+
+        for col in 2 .. 6 {
+            state[(6, col)] = Cell::Player { player: Player::Red };
+        }
+
+        assert_eq!(
+            state.get_winner(), None,
+            "Expected no winner with four in a row, got {:?}",
+            state.get_winner()
+        );
+
+        state[(6, 6)] = Cell::Player { player: Player::Red };
+
+        assert_eq!(
+            state.get_winner(), Some(Player::Red),
+            "Expected winner to be Red, got {:?}",
+            state.get_winner()
+        );
+    }
+
+    #[test]
+    /// Tests that the player who popped wins if the pop
+    /// connects four for both players at once (PopOut)
+    fn test_get_winner_simultaneous() {
+        let mut state = State::new().with_variant(crate::rules::Variant::PopOut);
+
+        // column 3, bottom to top: y R R R y y
+        // after popping, R R R y y lands on rows 5 to 1
+        for (row, player) in [(5, Player::Yellow), (4, Player::Red), (3, Player::Red), (2, Player::Red), (1, Player::Yellow), (0, Player::Yellow)] {
+            state[(row, 3)] = Cell::Player { player };
+        }
+
+        // a red row to complete with (5, 3) and a yellow
+        // row to complete with (2, 3) after popping
+        for col in [0, 1, 2] {
+            state[(5, col)] = Cell::Player { player: Player::Red };
+        }
+        for col in [4, 5, 6] {
+            state[(2, col)] = Cell::Player { player: Player::Yellow };
+        }
+
+        state.turn = Player::Yellow;
+        assert!(
+            state.can_pop(3),
+            "Expected yellow to be able to pop column 3, but it wasn't"
+        );
+
+        state.play(crate::board::Move::Pop(3));
+        let winner = state.get_winner();
+
+        assert_eq!(
+            winner, Some(Player::Yellow),
+            "Expected the popping player (Yellow) to win, got {:?}",
+            winner
+        );
+    }
+
+    #[test]
+    /// Tests that lines continue across the edge of a
+    /// cylinder, but not across the edge of a flat board
+    fn test_sequence_cylinder() {
+        let mut state = State::new();
+        let fields = vec![(5, 5), (5, 6), (5, 0), (5, 1)];
+
+        for (row, col) in fields.iter() {
+            state[(*row, *col)] = Cell::Player { player: Player::Red };
+        }
+
+        assert_eq!(
+            state.get_winner(), None,
+            "Expected no winner on a flat board, got {:?}",
+            state.get_winner()
+        );
+
+        let state = state.with_cylinder(true);
+        let seqs = sequences_from_position(&state, Player::Red, 5, 0);
+
+        assert_eq!(
+            seqs, vec![fields.clone()],
+            "Expected sequence {:?} in line order, got {:?}",
+            fields,
+            seqs
+        );
+
+        assert_eq!(
+            state.get_winner(), Some(Player::Red),
+            "Expected winner to be Red, got {:?}",
+            state.get_winner()
+        );
+    }
+
+    #[test]
+    /// Tests that a full row on a cylinder is found
+    /// once, without looping around forever
+    fn test_sequence_cylinder_ring() {
+        let mut state = State::new().with_cylinder(true);
+
+        for col in 0 .. COLS {
+            state[(5, col)] = Cell::Player { player: Player::Yellow };
+        }
+
+        let seqs = sequences_all(&state, Player::Yellow);
+
+        assert_eq!(
+            seqs, vec![(0 .. COLS).map(|col| (5, col)).collect::<Sequence>()],
+            "Expected a single sequence covering the row, got {:?}",
+            seqs
+        );
+    }
+
+    #[test]
+    /// Tests that the third player can win a three-player game
+    fn test_get_winner_three_players() {
+        let mut state = State::with_size(7, 9).with_players(3);
+
+        for row in 3 .. 7 {
+            state[(row, 4)] = Cell::Player { player: Player::Green };
+        }
+
+        assert_eq!(
+            state.get_winner(), Some(Player::Green),
+            "Expected winner to be Green, got {:?}",
+            state.get_winner()
+        );
+    }
+
+    #[test]
+    /// Tests that only drops completing a line are detected as wins
+    fn test_wins_after() {
+        let mut state = State::new();
+
+        // red: three in the bottom row, yellow: three in column 6
+        for col in 0 .. 3 {
+            state.drop(col, Player::Red);
+            state.drop(6, Player::Yellow);
+        }
+
+        let wins = (0 .. COLS).filter(|&col| state.wins_after(col)).collect::<Vec<_>>();
+
+        assert_eq!(
+            wins, vec![3],
+            "Expected only column 3 to win for red, got {:?}",
+            wins
+        );
+
+        state.drop(4, Player::Red);
+        let wins = (0 .. COLS).filter(|&col| state.wins_after(col)).collect::<Vec<_>>();
+
+        assert_eq!(
+            wins, vec![6],
+            "Expected only column 6 to win for yellow, got {:?}",
+            wins
+        );
+
+        for col in 0 .. COLS {
+            assert_eq!(
+                state.wins_after(col), state.played(Move::Drop(col)).get_winner().is_some(),
+                "Expected wins_after to match get_winner for column {}",
+                col
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that lines through a piece are found in
+    /// every direction, including around a cylinder
+    fn test_connects_at() {
+        let mut state = State::new().with_cylinder(true);
+        let fields = vec![(5, 5), (5, 6), (5, 0), (5, 1)];
+
+        for &(row, col) in &fields {
+            state[(row, col)] = Cell::Player { player: Player::Red };
+        }
+
+        for &(row, col) in &fields {
+            assert!(
+                state.connects_at(row, col),
+                "Expected the piece at {:?} to be part of a line, but it wasn't",
+                (row, col)
+            );
+        }
+
+        assert!(
+            state.has_connected(Player::Red),
+            "Expected red to have connected around the cylinder, but it didn't",
+        );
+
+        state.cylinder = false;
+
+        assert!(
+            !state.connects_at(5, 0) && !state.has_connected(Player::Red),
+            "Expected no line without a cylinder, but there was one",
+        );
+    }
+
+    #[test]
+    /// Tests that vertical lines longer than the board is
+    /// wide are found on a tall and narrow board
+    fn test_wins_after_tall() {
+        let mut state = State::with_size(10, 4).with_win_length(6);
+
+        // red: five in column 0, yellow: five in column 1
+        for _ in 0 .. 5 {
+            state.play(Move::Drop(0));
+            state.play(Move::Drop(1));
+        }
+
+        assert!(
+            state.wins_after(0) && state.played(Move::Drop(0)).get_winner() == Some(Player::Red),
+            "Expected dropping into column 0 to win for red, but it didn't",
+        );
+
+        assert!(
+            state.rules().wins_with(&state, Move::Drop(0)),
+            "Expected the rules to report the win in column 0, but they didn't",
+        );
+    }
+}
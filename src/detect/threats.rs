@@ -0,0 +1,207 @@
+use crate::board::{Move, Player, State};
+
+/// Whether a threat can be played right away
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum ThreatKind {
+    /// The threat's cell is the lowest empty cell of its
+    /// column, so a piece dropped there wins immediately
+    Immediate,
+
+    /// The threat's cell is further up its column and only
+    /// becomes playable once the cells below it are filled
+    Future,
+}
+
+/// An empty cell that would complete (at least) `win_length`
+/// pieces in a row for a player, including lines with gaps
+/// (e.g. "RR_R") and lines open on either end
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct Threat {
+    pub player: Player,
+    pub row: usize,
+    pub col: usize,
+
+    /// The row of the cell counted from the bottom, starting at 1
+    pub height: usize,
+    pub kind: ThreatKind,
+}
+
+impl Threat {
+    /// Returns whether the threat is on an odd row (counted from
+    /// the bottom, starting at 1). In a two-player game that is
+    /// played until the board is full, the first player can
+    /// usually realise odd threats and the second one even ones
+    /// (zugzwang), as they are the ones to fill those rows.
+    pub fn is_odd(&self) -> bool {
+        !self.height.is_multiple_of(2)
+    }
+
+    /// Returns whether the threat can be played right away
+    pub fn is_immediate(&self) -> bool {
+        self.kind == ThreatKind::Immediate
+    }
+}
+
+/// Returns all threats of the given player, column by column
+/// from left to right and bottom to top within each column
+pub fn threats(state: &State, player: Player) -> Vec<Threat> {
+    let rows = state.rows();
+    let mut threats = vec![];
+
+    for col in 0 .. state.cols() {
+        for row in (0 .. rows).rev() {
+            if !state[(row, col)].is_empty() || !state.completes_line(player, row, col) {
+                continue;
+            }
+
+            let playable = row == rows - 1 || !state[(row + 1, col)].is_empty();
+            threats.push(Threat {
+                player,
+                row,
+                col,
+                height: rows - row,
+                kind: if playable { ThreatKind::Immediate } else { ThreatKind::Future },
+            });
+        }
+    }
+
+    threats
+}
+
+/// Returns the threats of all players in the game, in turn order
+pub fn threats_all(state: &State) -> Vec<Threat> {
+    Player::ALL[.. state.players]
+        .iter()
+        .flat_map(|&player| threats(state, player))
+        .collect()
+}
+
+/// Orders the given moves of the current player by the immediate
+/// threats they are played into: drops onto the player's own threats
+/// (winning right away) first, then drops blocking a threat of any
+/// opponent, then all other moves in their original order
+pub fn order_moves(state: &State, moves: &mut [Move]) {
+    let player = state.player();
+
+    moves.sort_by_key(|&mv| {
+        let Move::Drop(col) = mv else {
+            return 2;
+        };
+
+        // the cell a piece dropped into the column lands on
+        let Some(row) = (0 .. state.rows()).rev().find(|&row| state[(row, col)].is_empty()) else {
+            return 2;
+        };
+
+        if state.completes_line(player, row, col) {
+            0
+        } else if Player::ALL[.. state.players].iter().any(|&p| p != player && state.completes_line(p, row, col)) {
+            1
+        } else {
+            2
+        }
+    });
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::board::Cell;
+
+    #[test]
+    /// Tests that a gap in a line is found as an immediate threat
+    fn test_threats_gap() {
+        let mut state = State::new();
+
+        // bottom row: R R _ R
+        for col in [0, 1, 3] {
+            state[(5, col)] = Cell::Player { player: Player::Red };
+        }
+
+        let threats = threats(&state, Player::Red);
+
+        assert_eq!(
+            threats, vec![Threat { player: Player::Red, row: 5, col: 2, height: 1, kind: ThreatKind::Immediate }],
+            "Expected a single immediate threat in the gap, got {:?}",
+            threats
+        );
+
+        assert!(
+            threats[0].is_odd(),
+            "Expected the bottom row to be odd, but it wasn't",
+        );
+    }
+
+    #[test]
+    /// Tests that threats above empty cells are future
+    /// threats and that their row parity is correct
+    fn test_threats_future() {
+        let mut state = State::new();
+
+        // a yellow diagonal from the bottom left, missing its
+        // top piece (row 2, col 3), with supporting red pieces
+        let supports = [(5, 1), (5, 2), (4, 2), (5, 3), (4, 3)];
+        for (row, col) in supports {
+            state[(row, col)] = Cell::Player { player: Player::Red };
+        }
+
+        for (row, col) in [(5, 0), (4, 1), (3, 2)] {
+            state[(row, col)] = Cell::Player { player: Player::Yellow };
+        }
+
+        let threats = threats(&state, Player::Yellow);
+
+        assert_eq!(
+            threats, vec![Threat { player: Player::Yellow, row: 2, col: 3, height: 4, kind: ThreatKind::Future }],
+            "Expected a single future threat, got {:?}",
+            threats
+        );
+
+        assert!(
+            !threats[0].is_odd(),
+            "Expected the fourth row to be even, but it wasn't",
+        );
+    }
+
+    #[test]
+    /// Tests that threats of all players are found
+    fn test_threats_all() {
+        let mut state = State::new();
+
+        for row in 3 .. 6 {
+            state[(row, 0)] = Cell::Player { player: Player::Red };
+            state[(row, 6)] = Cell::Player { player: Player::Yellow };
+        }
+
+        let threats = threats_all(&state);
+        let cells = threats.iter().map(|t| (t.player, t.row, t.col)).collect::<Vec<_>>();
+
+        assert_eq!(
+            cells, vec![(Player::Red, 2, 0), (Player::Yellow, 2, 6)],
+            "Expected a threat on top of both columns, got {:?}",
+            cells
+        );
+    }
+
+    #[test]
+    /// Tests that winning moves are ordered first,
+    /// followed by the moves blocking the opponent
+    fn test_order_moves() {
+        let mut state = State::new();
+
+        // red: three in column 0, yellow: three in the bottom row
+        for col in 1 .. 4 {
+            state.play(Move::Drop(0));
+            state.play(Move::Drop(col));
+        }
+
+        let mut moves = state.get_valid();
+        order_moves(&state, &mut moves);
+
+        assert_eq!(
+            moves, [0, 4, 1, 2, 3, 5, 6].map(Move::Drop),
+            "Expected the winning move, then the blocking one, got {:?}",
+            moves
+        );
+    }
+}
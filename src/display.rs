@@ -1,79 +1,268 @@
 use macroquad::{color::{self, Color}, shapes::{self, draw_rectangle}, text::{draw_text, measure_text}, window::{screen_height, screen_width}};
-use crate::{board::{Cell, Player, State, COLS, MAXIMIZER, ROWS}, detect::sequences_all};
+use crate::{board::{Cell, Move, Player, State, MAXIMIZER, MINIMIZER}, detect::{sequences_all, threats::Threat, Sequence}, eval::{difficulty::Difficulty, score::Score, search::SearchResult}, rules::{Variant, POP_TEN_TARGET}};
 
 pub const SIZE: f32 = 100.0;
 
 pub const MARGIN: f32 = 50.0;
 
-pub const WIDTH: f32 = COLS as f32 * SIZE + 2.0 * MARGIN;
-pub const HEIGHT: f32 = ROWS as f32 * SIZE + 2.0 * MARGIN;
+/// Returns the width of the window needed to
+/// display a board with the given number of columns
+pub fn width(cols: usize) -> f32 {
+    cols as f32 * SIZE + 2.0 * MARGIN
+}
+
+/// Returns the height of the window needed to
+/// display a board with the given number of rows
+pub fn height(rows: usize) -> f32 {
+    rows as f32 * SIZE + 2.0 * MARGIN
+}
+
+/// Returns the color of the given player's pieces
+pub fn player_color(player: Player) -> Color {
+    match player {
+        Player::Red => color::RED,
+        Player::Yellow => color::YELLOW,
+        Player::Green => color::GREEN,
+    }
+}
 
 pub async fn draw_board(state: &State) {
-    for row in 0 .. ROWS {
-        for col in 0 .. COLS {
+    for row in 0 .. state.rows() {
+        for col in 0 .. state.cols() {
             draw_cell(state, row, col).await;
         }
     }
+
+    if state.variant == Variant::PopTen {
+        draw_captured(state).await;
+    }
+}
+
+/// Draws the number of pieces each player collected
+/// (Pop 10) into the top margin
+pub async fn draw_captured(state: &State) {
+    let text = Player::ALL[.. state.players]
+        .iter()
+        .map(|player| {
+            let name = match player {
+                Player::Red => "Red",
+                Player::Yellow => "Yellow",
+                Player::Green => "Green",
+            };
+            format!("{}: {} / {}", name, state.captured[player.index()], POP_TEN_TARGET)
+        })
+        .collect::<Vec<_>>()
+        .join("    ");
+
+    let font_size = 24.0;
+    let text_size = measure_text(&text, None, font_size as _, 1.0);
+
+    draw_text(
+        &text,
+        screen_width() / 2. - text_size.width / 2.,
+        MARGIN / 2. + text_size.height / 2.,
+        font_size,
+        color::BLACK
+    );
+}
+
+/// Draws the engine's difficulty and its latest search (depth,
+/// score and principal variation, if any) into the bottom margin
+pub async fn draw_status(difficulty: Difficulty, info: Option<&SearchResult>) {
+    let text = match info {
+        Some(info) => format!("{}    depth {}    score {}    pv {}", difficulty, info.depth, info.score, info.pv_notation()),
+        None => format!("{}", difficulty),
+    };
+
+    let font_size = 24.0;
+    let text_size = measure_text(&text, None, font_size as _, 1.0);
+
+    draw_text(
+        &text,
+        screen_width() / 2. - text_size.width / 2.,
+        screen_height() - MARGIN / 2. + text_size.height / 2.,
+        font_size,
+        color::BLACK
+    );
+}
+
+/// Returns the label of a column's score in the analysis overlay,
+/// e.g. "W5" (win in five plies), "L3" (loss in three plies), "draw"
+/// (a score of zero) or the heuristic score (e.g. "+1.2")
+pub fn score_label(score: Score) -> String {
+    match score.plies_to_end() {
+        Some(plies) if score.is_win() => format!("W{}", plies),
+        Some(plies) => format!("L{}", plies),
+        None if score == Score::DRAW => "draw".to_string(),
+        None => format!("{:+.1}", score.0 as f32 / 100.0),
+    }
+}
+
+/// Draws the score of every column (from the perspective of the
+/// player to move) into the top margin. If a piece can be both
+/// dropped into and popped from a column, the better move is shown,
+/// with pops marked by a "p".
+pub async fn draw_analysis(state: &State, scores: &[(Move, Score)]) {
+    for col in 0 .. state.cols() {
+        let best = scores
+            .iter()
+            .filter(|(mv, _)| mv.col() == col)
+            .max_by_key(|(_, score)| *score);
+
+        let Some(&(mv, score)) = best else {
+            continue;
+        };
+
+        let text = match mv {
+            Move::Drop(_) => score_label(score),
+            Move::Pop(_) => format!("p{}", score_label(score)),
+        };
+
+        let font_size = 24.0;
+        let text_size = measure_text(&text, None, font_size as _, 1.0);
+
+        let color = if score.is_win() { color::DARKGREEN } else if score.is_loss() { color::MAROON } else { color::BLACK };
+
+        draw_text(
+            &text,
+            col as f32 * SIZE + MARGIN + SIZE / 2. - text_size.width / 2.,
+            MARGIN / 2. + text_size.height / 2.,
+            font_size,
+            color
+        );
+    }
 }
 
 pub async fn draw_cell(state: &State, row: usize, col: usize) {
     let cell = state[(row, col)];
     let color = match cell {
         Cell::Empty => color::WHITE,
-        Cell::Player { player } => match player {
-            Player::Red => color::RED,
-            Player::Yellow => color::YELLOW,
-        }
+        Cell::Player { player } => player_color(player),
     };
 
-    let size = 100.0;
+    let x = col as f32 * SIZE + MARGIN + SIZE / 2.0;
+    let y = row as f32 * SIZE + MARGIN + SIZE / 2.0;
 
-    let x = col as f32 * size + MARGIN + size / 2.0;
-    let y = row as f32 * size + MARGIN + size / 2.0;
-
-    shapes::draw_circle(x, y, size / 2.25, color);
+    shapes::draw_circle(x, y, SIZE / 2.25, color);
 }
 
 pub async fn draw_highlight(state: &State, col: usize) {
+    draw_column(state, col, player_color(state.player())).await;
+}
+
+/// Marks the cells of the given threats (see `threats_all`) in the
+/// color of their players: a dot for an immediate threat, a ring
+/// for one that only becomes playable later
+pub async fn draw_threats(threats: &[Threat]) {
+    for threat in threats {
+        let x = threat.col as f32 * SIZE + MARGIN + SIZE / 2.0;
+        let y = threat.row as f32 * SIZE + MARGIN + SIZE / 2.0;
+        let color = player_color(threat.player);
+
+        // threats of several players on one cell stay apart
+        let radius = SIZE / 4.0 - threat.player.index() as f32 * SIZE / 16.0;
+
+        match threat.is_immediate() {
+            true => shapes::draw_circle(x, y, radius, color),
+            false => shapes::draw_circle_lines(x, y, radius, 6.0, color),
+        }
+    }
+}
+
+/// Highlights the column recommended by the engine
+pub async fn draw_hint(state: &State, col: usize) {
+    draw_column(state, col, color::SKYBLUE).await;
+}
+
+/// Draws a translucent rectangle over the given column
+pub async fn draw_column(state: &State, col: usize, color: Color) {
     let x = col as f32 * SIZE + MARGIN;
     let y = 0.0;
 
-    let color = if state.player() == Player::Red {
-        Color::from_rgba(255, 0, 0, 128)
-    } else {
-        Color::from_rgba(255, 255, 0, 128)
+    let color = Color {
+        a: 0.5,
+        ..color
     };
 
     shapes::draw_rectangle(
         x, y, 
-        SIZE, HEIGHT, 
+        SIZE, height(state.rows()), 
         color
     )
 }
 
+/// Returns the share of the evaluation bar filled with the
+/// MAXIMIZER's color for the given score (from the MAXIMIZER's
+/// perspective): all of it for a won game, none of it for a lost
+/// one and half of it for an even position
+pub fn eval_bar_share(score: Score) -> f32 {
+    if score.is_win() {
+        1.0
+    } else if score.is_loss() {
+        0.0
+    } else {
+        0.5 + 0.5 * (score.0 as f32 / 2000.0).tanh()
+    }
+}
+
+/// Draws the evaluation bar into the left margin, filled
+/// from the bottom with the MAXIMIZER's color according
+/// to the given score (from the MAXIMIZER's perspective)
+pub async fn draw_eval_bar(state: &State, score: Score) {
+    let (x, width) = (MARGIN * 0.3, MARGIN * 0.4);
+    let (y, height) = (MARGIN, state.rows() as f32 * SIZE);
+    let share = eval_bar_share(score);
+
+    draw_rectangle(x, y, width, height, player_color(MINIMIZER));
+    draw_rectangle(x, y + height * (1.0 - share), width, height * share, player_color(MAXIMIZER));
+}
+
+/// Draws a line through the given sequence of cells. Lines that
+/// wrap around a cylinder are split where they cross the edge,
+/// with both parts running off the board.
+pub async fn draw_sequence(seq: &Sequence, color: Color) {
+    let center = |row: f32, col: f32| (
+        col * SIZE + MARGIN + SIZE / 2.0,
+        row * SIZE + MARGIN + SIZE / 2.0,
+    );
+
+    for pair in seq.windows(2) {
+        let (r1, c1) = (pair[0].0 as f32, pair[0].1 as f32);
+        let (r2, c2) = (pair[1].0 as f32, pair[1].1 as f32);
+
+        let mut segments = vec![((r1, c1), (r2, c2))];
+
+        // neighbouring cells are at most one column apart,
+        // unless the line wraps around the edge
+        if (c2 - c1).abs() > 1.0 {
+            let step = if c2 > c1 { -1.0 } else { 1.0 };
+            segments = vec![((r1, c1), (r2, c1 + step)), ((r1, c2 - step), (r2, c2))];
+        }
+
+        for ((r1, c1), (r2, c2)) in segments {
+            let (x1, y1) = center(r1, c1);
+            let (x2, y2) = center(r2, c2);
+
+            shapes::draw_line(x1, y1, x2, y2, 12.0, color);
+        }
+    }
+}
+
 pub async fn draw_game_over(state: &State, winner: Option<Player>) {
-    draw_board(&state).await;
+    draw_board(state).await;
     
     if let Some(player) = winner {
         let seqs = sequences_all(state, player)
             .into_iter()
-            .filter(|seq| seq.len() >= 4);
+            .filter(|seq| seq.len() >= state.win_length);
 
         for seq in seqs {
-            let start = seq[0];
-            let end = seq[seq.len() - 1];
-
-            let x1 = start.1 as f32 * SIZE + MARGIN + SIZE / 2.0;
-            let y1 = start.0 as f32 * SIZE + MARGIN + SIZE / 2.0;
-
-            let x2 = end.1 as f32 * SIZE + MARGIN + SIZE / 2.0;
-            let y2 = end.0 as f32 * SIZE + MARGIN + SIZE / 2.0;
-
-            shapes::draw_line(x1, y1, x2, y2, 12.0, if player == MAXIMIZER {
-                color::YELLOW
-            } else {
-                color::RED
-            });
+            // use a color that stands out against the player's pieces
+            draw_sequence(&seq, match player {
+                Player::Red => color::YELLOW,
+                Player::Yellow => color::RED,
+                Player::Green => color::BLUE,
+            }).await;
         }
     }
     
@@ -82,6 +271,7 @@ pub async fn draw_game_over(state: &State, winner: Option<Player>) {
     let text = match winner {
         Some(Player::Red) => "Red wins!",
         Some(Player::Yellow) => "Yellow wins!",
+        Some(Player::Green) => "Green wins!",
         None => "It's a draw!",
     };
 
@@ -108,3 +298,26 @@ pub async fn draw_game_over(state: &State, winner: Option<Player>) {
         color::BLACK
     );
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests that the bar is filled according to the
+    /// score, from the MAXIMIZER's perspective
+    fn test_eval_bar_share() {
+        assert_eq!(
+            (eval_bar_share(Score::win_in(5)), eval_bar_share(Score::loss_in(2)), eval_bar_share(Score::DRAW)), (1.0, 0.0, 0.5),
+            "Expected a full bar for a win, an empty one for a loss and half of one for a draw",
+        );
+
+        let (ahead, behind) = (eval_bar_share(Score(150)), eval_bar_share(Score(-150)));
+
+        assert!(
+            ahead > 0.5 && ahead < 1.0 && (ahead + behind - 1.0).abs() < 1e-6,
+            "Expected a symmetric share between half and full for an advantage, got {} / {}",
+            ahead, behind
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    board::{Board, Move, State},
+    config::Config,
+    eval::{evaluator::Zero, random::Rng, score::Score, search::SearchLimits, table::TranspositionTable, tablebase::{Tablebase, MAX_EMPTY, MAX_POSITIONS}},
+};
+
+/// The default tablebase file
+pub const TABLEBASE_FILE: &str = "tablebase.bin";
+
+/// The default number of positions compared with the solver
+pub const SAMPLES: usize = 100;
+
+/// What the `tablebase` subcommand does
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Action {
+    /// solve all positions and write the tablebase
+    #[default]
+    Generate,
+
+    /// compare the tablebase with the solver
+    Verify,
+}
+
+/// The configuration of the tablebase generation (or verification)
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndgameConfig {
+    /// the board (and rules) of the tablebase
+    pub board: Config,
+
+    pub action: Action,
+
+    /// the moves leading to the root position, the
+    /// positions reachable from it are solved
+    pub root: Vec<Move>,
+
+    /// the maximum number of empty cells of a solved position
+    pub empty: usize,
+
+    /// the maximum number of positions visited by the generation
+    pub positions: usize,
+
+    /// the tablebase file written (or verified)
+    pub file: String,
+
+    /// the number of (random) positions compared with the solver
+    pub samples: usize,
+
+    /// the seed of the random positions
+    pub seed: Option<u64>,
+}
+
+impl EndgameConfig {
+    /// Parses the configuration from the given command line
+    /// arguments (excluding the program name and the subcommand),
+    /// starting with the action, `generate` (default) or `verify`
+    ///
+    /// Supported arguments (in addition to the board arguments of `Config`):
+    ///     --empty <n>             maximum empty cells of a solved position (default: 10)
+    ///     --root <moves>          moves leading to the root position, e.g. "4 4 3"
+    ///     --positions <n>         maximum positions visited (default: 20000000)
+    ///     --file <file>           tablebase file (default: tablebase.bin)
+    ///     --samples <n>           positions compared with the solver (default: 100)
+    ///     --seed <n>              seed of the compared positions
+    pub fn from_args<I>(args: I) -> Result<EndgameConfig, String>
+    where
+        I: IntoIterator<Item = String>
+    {
+        let mut config = EndgameConfig::default();
+        let mut board_args = vec![];
+        let mut args = args.into_iter().peekable();
+
+        match args.peek().map(String::as_str) {
+            Some("generate") => { args.next(); }
+            Some("verify") => { args.next(); config.action = Action::Verify; }
+            _ => (),
+        }
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            let number = |value: String| value.trim().parse::<usize>().map_err(|_| format!("invalid number '{}'", value));
+
+            match arg.as_str() {
+                "--empty" => config.empty = number(value()?)?,
+                "--root" => config.root = value()?.split_whitespace().map(str::parse).collect::<Result<_, _>>()?,
+                "--positions" => config.positions = number(value()?)?.max(1),
+                "--file" => config.file = value()?,
+                "--samples" => config.samples = number(value()?)?.max(1),
+                "--seed" => config.seed = Some(number(value()?)? as u64),
+                _ => board_args.push(arg),
+            }
+        }
+
+        config.board = Config::from_args(board_args)?;
+        if config.board.players != 2 {
+            return Err("tablebases are generated for two-player games".to_string());
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the root position, i.e. the position after the root moves
+    pub fn root(&self) -> Result<State, String> {
+        let mut board = Board::from_state(self.board.state());
+
+        for &mv in &self.root {
+            if board.is_over() || !board.play(mv) {
+                return Err(format!("illegal root move '{}'", mv));
+            }
+        }
+
+        Ok(board.state().clone())
+    }
+}
+
+impl Default for EndgameConfig {
+    fn default() -> Self {
+        EndgameConfig {
+            board: Config::default(),
+            action: Action::default(),
+            root: vec![],
+            empty: MAX_EMPTY,
+            positions: MAX_POSITIONS,
+            file: TABLEBASE_FILE.to_string(),
+            samples: SAMPLES,
+            seed: None,
+        }
+    }
+}
+
+/// A position whose result in the tablebase (if any)
+/// differs from the solver's one
+#[derive(Clone, PartialEq, Debug)]
+pub struct Mismatch {
+    pub state: State,
+    pub tablebase: Option<Score>,
+    pub solver: Score,
+}
+
+/// Compares the results of the tablebase with the solver's ones (a
+/// search with the `Zero` evaluator to the end of the game) for the
+/// given number of random positions reachable from the root with at
+/// most the tablebase's number of empty cells. Returns the number of
+/// positions compared and the ones that differ.
+pub fn verify(tablebase: &Tablebase, root: &State, samples: usize, threads: usize, rng: &mut Rng) -> (usize, Vec<Mismatch>) {
+    let tt = TranspositionTable::new();
+    let (mut compared, mut mismatches) = (0, vec![]);
+
+    // games ending before reaching the tablebase don't yield a position
+    for _ in 0 .. samples * 10 {
+        if compared == samples {
+            break;
+        }
+
+        let mut state = root.clone();
+        while !state.is_terminal() && state.empty_cells() > tablebase.max_empty {
+            let moves = state.get_valid();
+            state.play(moves[rng.below(moves.len())]);
+        }
+
+        if state.is_terminal() {
+            continue;
+        }
+
+        // entries of other positions can lead the solver to a slower
+        // (but still winning) line, so every position starts afresh
+        tt.clear();
+
+        let probed = tablebase.probe(&state, state.canonical_hash());
+        let solved = state.best(SearchLimits::depth(state.empty_cells()), state.player(), &Zero, &tt, threads).score;
+
+        compared += 1;
+        if probed != Some(solved) {
+            mismatches.push(Mismatch { state, tablebase: probed, solver: solved });
+        }
+    }
+
+    (compared, mismatches)
+}
+
+/// Generates the tablebase and writes it to its file or, when
+/// verifying, reads it and compares it with the solver
+pub fn run(config: &EndgameConfig) -> Result<(), String> {
+    let root = config.root()?;
+
+    match config.action {
+        Action::Generate => {
+            println!("solving positions with at most {} empty cells", config.empty);
+            let tablebase = Tablebase::generate(&root, config.empty, config.positions, |positions| println!("{} positions", positions))?;
+
+            tablebase.save(&config.file)?;
+            println!("{} positions written to {}", tablebase.len(), config.file);
+        }
+        Action::Verify => {
+            let tablebase = Tablebase::load(&config.file)?;
+            if !tablebase.fits(&root) {
+                return Err(format!("'{}' is a tablebase of another board", config.file));
+            }
+
+            let mut rng = config.seed.map_or_else(Rng::from_time, Rng::new);
+            let (compared, mismatches) = verify(&tablebase, &root, config.samples, config.board.threads, &mut rng);
+
+            for mismatch in &mismatches {
+                let probed = mismatch.tablebase.map_or("none".to_string(), |score| score.to_string());
+                // the board ends with a line break, a blank line separates the mismatches
+                println!("{}tablebase {}, solver {}\n", mismatch.state, probed, mismatch.solver);
+            }
+
+            println!("{} positions compared, {} differ", compared, mismatches.len());
+            if !mismatches.is_empty() {
+                return Err("the tablebase differs from the solver".to_string());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = EndgameConfig::from_args(args(&["verify", "--empty", "6", "--root", "4 4 3", "--size", "5x4"])).unwrap();
+
+        assert_eq!(
+            (config.action, config.empty, config.root.len(), config.board.cols), (Action::Verify, 6, 3, 5),
+            "Expected the tablebase and board arguments to be parsed, got {:?}",
+            config
+        );
+
+        for invalid in [&["--root", "4 x"][..], &["--root", "9"], &["--empty", "-1"], &["--players", "3"]] {
+            let config = EndgameConfig::from_args(args(invalid));
+
+            assert!(
+                config.as_ref().map_or(true, |config| config.root().is_err()),
+                "Expected {:?} to be rejected, but it wasn't",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that a generated tablebase agrees with the solver
+    fn test_verify() {
+        let root = State::with_size(4, 5).with_win_length(3).played(Move::Drop(2));
+        let tablebase = Tablebase::generate(&root, 9, MAX_POSITIONS, |_| ()).unwrap();
+
+        let (compared, mismatches) = verify(&tablebase, &root, 20, 1, &mut Rng::new(3));
+
+        assert!(
+            compared > 0 && mismatches.is_empty(),
+            "Expected the tablebase to agree with the solver, got {} of {} positions differing: {:?}",
+            mismatches.len(), compared, mismatches.first()
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use crate::board::{Move, State};
+
+use super::{evaluator::Evaluator, mcts::{MctsConfig, Playout}, random::Rng, search::{SearchLimits, SearchResult}, table::TranspositionTable};
+
+/// The configuration of the engine, i.e. how deep (and long) it
+/// searches and how much it deviates from the best move
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EngineConfig {
+    pub limits: SearchLimits,
+
+    /// the maximum noise added to (or subtracted from)
+    /// every heuristic score, in hundredths (see `Score`)
+    pub noise: i32,
+
+    /// the probability of choosing a sub-optimal,
+    /// but non-losing move instead of the best one
+    pub blunder: f32,
+}
+
+impl EngineConfig {
+    /// Returns whether the engine always plays the best move it finds
+    pub fn is_exact(&self) -> bool {
+        self.noise == 0 && self.blunder <= 0.0
+    }
+}
+
+/// The difficulty levels of the engine, from
+/// easiest to hardest (see `Difficulty::engine`)
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Default)]
+pub enum Difficulty {
+    Beginner,
+    Easy,
+    Medium,
+    #[default]
+    Hard,
+    Expert,
+    Perfect,
+}
+
+impl Difficulty {
+    /// All difficulty levels, from easiest to hardest
+    pub const ALL: [Difficulty; 6] = [
+        Difficulty::Beginner, Difficulty::Easy, Difficulty::Medium,
+        Difficulty::Hard, Difficulty::Expert, Difficulty::Perfect,
+    ];
+
+    /// Returns the engine configuration of the difficulty level
+    pub fn engine(&self) -> EngineConfig {
+        let (limits, noise, blunder) = match self {
+            Difficulty::Beginner => (SearchLimits::depth(1), 300, 0.4),
+            Difficulty::Easy => (SearchLimits::depth(2), 150, 0.25),
+            Difficulty::Medium => (SearchLimits::depth(4), 50, 0.1),
+            Difficulty::Hard => (SearchLimits::depth(6), 0, 0.0),
+            Difficulty::Expert => (SearchLimits::depth(8).with_time(Duration::from_secs(5)), 0, 0.0),
+            Difficulty::Perfect => (SearchLimits::depth(64).with_time(Duration::from_secs(10)), 0, 0.0),
+        };
+
+        EngineConfig { limits, noise, blunder }
+    }
+
+    /// Returns the Monte Carlo tree search configuration of the difficulty level
+    pub fn mcts(&self) -> MctsConfig {
+        match self {
+            Difficulty::Beginner => MctsConfig::iterations(100).with_playout(Playout::Random),
+            Difficulty::Easy => MctsConfig::iterations(500).with_playout(Playout::Random),
+            Difficulty::Medium => MctsConfig::iterations(2_000),
+            Difficulty::Hard => MctsConfig::iterations(20_000),
+            Difficulty::Expert => MctsConfig::iterations(200_000).with_time(Duration::from_secs(5)),
+            Difficulty::Perfect => MctsConfig::iterations(usize::MAX).with_time(Duration::from_secs(10)),
+        }
+    }
+
+    /// Returns the name used to select the
+    /// difficulty on the command line
+    pub fn key(&self) -> &'static str {
+        match self {
+            Difficulty::Beginner => "beginner",
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+            Difficulty::Expert => "expert",
+            Difficulty::Perfect => "perfect",
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    /// Display the name of the difficulty (e.g. "Medium")
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Difficulty::Beginner => "Beginner",
+            Difficulty::Easy => "Easy",
+            Difficulty::Medium => "Medium",
+            Difficulty::Hard => "Hard",
+            Difficulty::Expert => "Expert",
+            Difficulty::Perfect => "Perfect",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    /// Parse a difficulty from its command line name (see `key`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Difficulty::ALL
+            .into_iter()
+            .find(|difficulty| difficulty.key() == s.trim())
+            .ok_or_else(|| {
+                let keys = Difficulty::ALL.map(|difficulty| difficulty.key()).join(", ");
+                format!("unknown difficulty '{}', expected one of: {}", s, keys)
+            })
+    }
+}
+
+impl State {
+    /// Chooses the engine's move for the current player. An exact engine
+    /// plays the best move found by `search` (reporting its progress to
+    /// the given callback). Any other engine scores every move (see
+    /// `analyze_result`, reporting its result to the callback), adds noise to
+    /// the heuristic scores and, with the configured probability, plays
+    /// any other move not known to lose.
+    #[allow(clippy::too_many_arguments)]
+    pub fn choose(
+        &self,
+        engine: &EngineConfig,
+        evaluator: &dyn Evaluator,
+        tt: &TranspositionTable,
+        threads: usize,
+        history: &[u64],
+        rng: &mut Rng,
+        mut progress: impl FnMut(&SearchResult),
+    ) -> Option<Move> {
+        if engine.is_exact() {
+            return self.search(engine.limits, self.player(), evaluator, tt, threads, history, progress).best_move;
+        }
+
+        let (mut scores, result) = self.analyze_result(engine.limits.depth, evaluator, tt, threads, history);
+        if result.best_move.is_some() {
+            progress(&result);
+        }
+
+        for (_, score) in scores.iter_mut() {
+            if !score.is_decided() && engine.noise > 0 {
+                score.0 += rng.below(2 * engine.noise as usize + 1) as i32 - engine.noise;
+            }
+        }
+
+        let &(best, _) = scores.iter().max_by_key(|(_, score)| *score)?;
+
+        let alternatives = scores
+            .iter()
+            .filter(|&&(mv, score)| mv != best && !score.is_loss())
+            .map(|&(mv, _)| mv)
+            .collect::<Vec<_>>();
+
+        if !alternatives.is_empty() && rng.next_f32() < engine.blunder {
+            return Some(alternatives[rng.below(alternatives.len())]);
+        }
+
+        Some(best)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{board::{Cell, Player}, eval::evaluator::Windows};
+
+    #[test]
+    /// Tests that harder levels search deeper with less imperfection
+    fn test_levels() {
+        for pair in Difficulty::ALL.windows(2) {
+            let (easier, harder) = (pair[0].engine(), pair[1].engine());
+
+            assert!(
+                easier.limits.depth <= harder.limits.depth && easier.noise >= harder.noise && easier.blunder >= harder.blunder
+                    && pair[0].mcts().iterations <= pair[1].mcts().iterations,
+                "Expected {} to be easier than {}, but it wasn't",
+                pair[0], pair[1]
+            );
+        }
+
+        assert!(
+            Difficulty::Perfect.engine().is_exact() && !Difficulty::Beginner.engine().is_exact(),
+            "Expected only the harder levels to be exact, but they weren't",
+        );
+    }
+
+    #[test]
+    /// Tests that an imperfect engine never chooses a losing move
+    /// (here: not blocking a line of three) when there are others
+    fn test_choose_non_losing() {
+        let mut state = State::new();
+        for row in 3 .. 6 {
+            state[(row, 0)] = Cell::Player { player: Player::Yellow };
+        }
+        state[(5, 3)] = Cell::Player { player: Player::Red };
+        state[(5, 4)] = Cell::Player { player: Player::Red };
+        state.turn = Player::Red;
+
+        let engine = EngineConfig { limits: SearchLimits::depth(1), noise: 300, blunder: 1.0 };
+        let mut rng = Rng::new(1);
+
+        for _ in 0 .. 10 {
+            let mv = state.choose(&engine, &Windows, &TranspositionTable::new(), 1, &[], &mut rng, |_| ());
+
+            assert_eq!(
+                mv, Some(Move::Drop(0)),
+                "Expected the only non-losing move to be chosen, got {:?}",
+                mv
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that an imperfect engine reports the
+    /// statistics of the search it actually ran
+    fn test_choose_progress() {
+        let state = State::new().played(Move::Drop(3));
+        let engine = Difficulty::Medium.engine();
+
+        let mut reports = vec![];
+        state.choose(&engine, &Windows, &TranspositionTable::new(), 1, &[], &mut Rng::new(1), |result| reports.push(result.clone()));
+
+        assert!(
+            reports.len() == 1 && reports[0].nodes > 0 && reports[0].pv.first() == reports[0].best_move.as_ref(),
+            "Expected a single report of the search, got {:?}",
+            reports
+        );
+    }
+
+    #[test]
+    fn test_difficulty_from_str() {
+        for difficulty in Difficulty::ALL {
+            assert_eq!(
+                difficulty.key().parse::<Difficulty>(), Ok(difficulty),
+                "Expected {} to be parsed as {:?}, but it wasn't",
+                difficulty.key(), difficulty,
+            );
+        }
+
+        assert!(
+            "bogus".parse::<Difficulty>().is_err(),
+            "Expected an unknown difficulty to be rejected, but it wasn't",
+        );
+    }
+}
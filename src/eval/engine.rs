@@ -0,0 +1,240 @@
+use std::{fmt, str::FromStr, sync::{atomic::AtomicBool, Arc}};
+
+use crate::board::{Move, State};
+
+use super::{difficulty::{Difficulty, EngineConfig}, evaluator::Evaluator, mcts::Mcts, random::Rng, search::{SearchLimits, SearchResult}, table::TranspositionTable};
+
+/// A computer opponent, choosing moves for any player
+pub trait Engine: Send {
+    /// Returns the (display) name of the engine
+    fn name(&self) -> &'static str;
+
+    /// Chooses a move for the current player of the given state,
+    /// reporting the progress of the search to the given callback.
+    /// Returns `None` if there are no legal moves.
+    fn choose(&mut self, state: &State, progress: &mut dyn FnMut(&SearchResult)) -> Option<Move>;
+
+    /// Changes the strength of the engine
+    fn set_difficulty(&mut self, difficulty: Difficulty);
+
+    /// Sets the hashes of the positions of the game so far (see
+    /// `Board::history`), which the engine may take into account
+    /// for repetitions
+    fn set_history(&mut self, _history: &[u64]) {}
+
+    /// Searches the given state on the opponent's turn ("pondering")
+    /// until the given flag is set, so that the engine finds its reply
+    /// faster (at the same strength) once the opponent moved. Returns
+    /// the result of the search, whose principal variation predicts
+    /// the opponent's move and the reply, if the engine ponders at all.
+    fn ponder(&mut self, _state: &State, _stop: &AtomicBool) -> Option<SearchResult> {
+        None
+    }
+}
+
+/// The alpha-beta (negamax / paranoid) engine (see `State::choose`)
+pub struct Negamax {
+    pub engine: EngineConfig,
+    pub evaluator: &'static dyn Evaluator,
+    pub tt: Arc<TranspositionTable>,
+    pub threads: usize,
+    pub rng: Rng,
+
+    /// the hashes of the positions of the game so far (see `Engine::set_history`)
+    pub history: Vec<u64>,
+}
+
+impl Negamax {
+    /// Returns a negamax engine of the given difficulty
+    pub fn new(difficulty: Difficulty, evaluator: &'static dyn Evaluator, tt: Arc<TranspositionTable>, threads: usize) -> Negamax {
+        Negamax {
+            engine: difficulty.engine(),
+            evaluator,
+            tt,
+            threads,
+            rng: Rng::from_time(),
+            history: vec![],
+        }
+    }
+
+    /// Returns this engine with the given random number generator
+    /// (of its noise and blunders), e.g. to replay its choices
+    pub fn with_rng(self, rng: Rng) -> Negamax {
+        Negamax { rng, ..self }
+    }
+}
+
+impl Engine for Negamax {
+    fn name(&self) -> &'static str {
+        "Negamax"
+    }
+
+    fn choose(&mut self, state: &State, progress: &mut dyn FnMut(&SearchResult)) -> Option<Move> {
+        state.choose(&self.engine, self.evaluator, &self.tt, self.threads, &self.history, &mut self.rng, progress)
+    }
+
+    fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.engine = difficulty.engine();
+    }
+
+    fn set_history(&mut self, history: &[u64]) {
+        self.history = history.to_vec();
+    }
+
+    /// Searches one ply deeper than the engine does, filling the table
+    /// with the positions after every move of the opponent. Only games
+    /// of two players are pondered, as the search of more players
+    /// doesn't use the table.
+    fn ponder(&mut self, state: &State, stop: &AtomicBool) -> Option<SearchResult> {
+        if state.players != 2 || state.is_terminal() {
+            return None;
+        }
+
+        let limits = SearchLimits::depth(self.engine.limits.depth + 1);
+        Some(state.search_until(limits, state.player(), self.evaluator, &self.tt, self.threads, &self.history, stop))
+    }
+}
+
+/// All available engines, e.g. for
+/// selecting one on the command line
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Default)]
+pub enum EngineKind {
+    #[default]
+    Negamax,
+    Mcts,
+}
+
+impl EngineKind {
+    /// All available engines, e.g. for listing them
+    pub const ALL: [EngineKind; 2] = [EngineKind::Negamax, EngineKind::Mcts];
+
+    /// Returns a new engine of this kind, making its random choices
+    /// with the given generator. The evaluator, table and threads
+    /// are only used by engines that make use of them.
+    pub fn create(
+        &self,
+        difficulty: Difficulty,
+        evaluator: &'static dyn Evaluator,
+        tt: Arc<TranspositionTable>,
+        threads: usize,
+        rng: Rng,
+    ) -> Box<dyn Engine> {
+        match self {
+            EngineKind::Negamax => Box::new(Negamax::new(difficulty, evaluator, tt, threads).with_rng(rng)),
+            EngineKind::Mcts => Box::new(Mcts::new(difficulty.mcts()).with_rng(rng)),
+        }
+    }
+
+    /// Returns the name used to select the
+    /// engine on the command line
+    pub fn key(&self) -> &'static str {
+        match self {
+            EngineKind::Negamax => "negamax",
+            EngineKind::Mcts => "mcts",
+        }
+    }
+}
+
+impl fmt::Display for EngineKind {
+    /// Display the name of the engine (e.g. "MCTS")
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EngineKind::Negamax => "Negamax",
+            EngineKind::Mcts => "MCTS",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for EngineKind {
+    type Err = String;
+
+    /// Parse an engine from its command line name (see `key`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EngineKind::ALL
+            .into_iter()
+            .find(|kind| kind.key() == s.trim())
+            .ok_or_else(|| {
+                let keys = EngineKind::ALL.map(|kind| kind.key()).join(", ");
+                format!("unknown engine '{}', expected one of: {}", s, keys)
+            })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{board::{Cell, Player}, eval::evaluator::Windows};
+
+    #[test]
+    /// Tests that every engine takes an immediate win
+    fn test_engines_win() {
+        let mut state = State::new();
+        for col in 0 .. 3 {
+            state[(5, col)] = Cell::Player { player: Player::Yellow };
+            state[(4, col)] = Cell::Player { player: Player::Red };
+        }
+        state.turn = Player::Yellow;
+
+        for kind in EngineKind::ALL {
+            let mut engine = kind.create(Difficulty::Medium, &Windows, Arc::new(TranspositionTable::with_entries(1 << 12)), 1, Rng::new(1));
+            engine.set_difficulty(Difficulty::Hard);
+
+            let mv = engine.choose(&state, &mut |_| ());
+
+            assert_eq!(
+                mv, Some(Move::Drop(3)),
+                "Expected {} to win right away, got {:?}",
+                kind, mv
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that pondering on the opponent's turn lets the
+    /// engine find the same reply with fewer positions searched
+    fn test_ponder() {
+        let engine = |tt| Negamax { engine: EngineConfig { limits: SearchLimits::depth(4), noise: 0, blunder: 0.0 }, evaluator: &Windows, tt, threads: 1, rng: Rng::new(1), history: vec![] };
+        let (mut pondering, mut fresh) = (engine(Arc::new(TranspositionTable::new())), engine(Arc::new(TranspositionTable::new())));
+
+        let mut state = State::new();
+        state.play(Move::Drop(3));
+
+        let prediction = pondering.ponder(&state, &AtomicBool::new(false)).and_then(|result| result.best_move);
+        state.play(prediction.expect("a predicted move"));
+
+        let replies = [&mut pondering, &mut fresh].map(|engine| {
+            let mut nodes = 0;
+            (engine.choose(&state, &mut |result| nodes = result.nodes), nodes)
+        });
+
+        assert_eq!(
+            replies[0].0, replies[1].0,
+            "Expected the same reply with and without pondering, got {:?}",
+            replies
+        );
+
+        assert!(
+            replies[0].1 < replies[1].1,
+            "Expected fewer positions searched after pondering, got {:?}",
+            replies
+        );
+    }
+
+    #[test]
+    fn test_engine_kind_from_str() {
+        for kind in EngineKind::ALL {
+            assert_eq!(
+                kind.key().parse::<EngineKind>(), Ok(kind),
+                "Expected {} to be parsed as {:?}, but it wasn't",
+                kind.key(), kind,
+            );
+        }
+
+        assert!(
+            "bogus".parse::<EngineKind>().is_err(),
+            "Expected an unknown engine to be rejected, but it wasn't",
+        );
+    }
+}
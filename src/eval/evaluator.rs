@@ -0,0 +1,263 @@
+use std::{fmt, str::FromStr};
+
+use crate::board::{Player, State};
+
+use super::{params::Params, random::stable_hash};
+
+/// A static evaluation function used by the search. Evaluations
+/// are from the perspective of the given player: positive scores
+/// favor the player, `WIN_SCORE` means they won and `-WIN_SCORE`
+/// that any opponent won.
+pub trait Evaluator: Sync {
+    /// Returns the (display) name of the evaluator
+    fn name(&self) -> &'static str;
+
+    /// Returns the evaluation of the given state
+    /// from the perspective of the given player
+    fn evaluate(&self, state: &State, player: Player) -> f32;
+
+    /// Returns a key that differs between evaluators scoring
+    /// positions differently (e.g. by their parameters) and stays
+    /// the same across runs (see `stable_hash`), e.g. to tell which
+    /// evaluator the scores of a cache file are from
+    fn fingerprint(&self) -> u64 {
+        stable_hash(self.name().bytes().map(u64::from))
+    }
+}
+
+/// Scores contiguous sequences by their length and the positional
+/// weights of their cells with the given parameters (see `evaluate_for`)
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Weights(pub Params);
+
+/// Scores every window of `win_length` cells, threats, their
+/// parity and double threats (see `evaluate_windows_for`)
+pub struct Windows;
+
+/// Scores threats, their parity and double
+/// threats only (see `evaluate_threats_for`)
+pub struct Threats;
+
+/// Scores decided games only, every other position is
+/// a draw, which makes the search a pure solver
+pub struct Zero;
+
+impl Weights {
+    /// The evaluator with the hand-chosen parameters
+    pub const DEFAULT: Weights = Weights(Params::DEFAULT);
+
+    /// Returns an evaluator with the given parameters that lives for
+    /// the rest of the program, like the evaluators of `EvaluatorKind`
+    /// (e.g. for parameters read from a file at startup)
+    pub fn leak(params: Params) -> &'static Weights {
+        Box::leak(Box::new(Weights(params)))
+    }
+}
+
+impl Evaluator for Weights {
+    fn name(&self) -> &'static str {
+        "Weights"
+    }
+
+    fn evaluate(&self, state: &State, player: Player) -> f32 {
+        state.evaluate_for(player, &self.0)
+    }
+
+    fn fingerprint(&self) -> u64 {
+        let params = self.0.values().map(|value| value.to_bits() as u64);
+        stable_hash(self.name().bytes().map(u64::from).chain(params))
+    }
+}
+
+impl Evaluator for Windows {
+    fn name(&self) -> &'static str {
+        "Windows"
+    }
+
+    fn evaluate(&self, state: &State, player: Player) -> f32 {
+        state.evaluate_windows_for(player)
+    }
+}
+
+impl Evaluator for Threats {
+    fn name(&self) -> &'static str {
+        "Threats"
+    }
+
+    fn evaluate(&self, state: &State, player: Player) -> f32 {
+        state.evaluate_threats_for(player)
+    }
+}
+
+impl Evaluator for Zero {
+    fn name(&self) -> &'static str {
+        "Zero"
+    }
+
+    fn evaluate(&self, state: &State, player: Player) -> f32 {
+        state.outcome_for(player).unwrap_or(0.0)
+    }
+}
+
+/// All available evaluators, e.g. for
+/// selecting one on the command line
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Default)]
+pub enum EvaluatorKind {
+    Weights,
+    #[default]
+    Windows,
+    Threats,
+    Zero,
+    #[cfg(feature = "nn")]
+    Network,
+}
+
+impl EvaluatorKind {
+    /// All available evaluators, e.g. for listing them
+    pub const ALL: &[EvaluatorKind] = &[
+        EvaluatorKind::Weights, EvaluatorKind::Windows, EvaluatorKind::Threats, EvaluatorKind::Zero,
+        #[cfg(feature = "nn")]
+        EvaluatorKind::Network,
+    ];
+
+    /// Returns the evaluator
+    pub fn evaluator(&self) -> &'static dyn Evaluator {
+        match self {
+            EvaluatorKind::Weights => &Weights::DEFAULT,
+            EvaluatorKind::Windows => &Windows,
+            EvaluatorKind::Threats => &Threats,
+            EvaluatorKind::Zero => &Zero,
+            #[cfg(feature = "nn")]
+            EvaluatorKind::Network => &super::nn::Neural,
+        }
+    }
+
+    /// Returns the evaluator using the given parameters, if any.
+    /// Only the weights evaluator takes parameters (see `takes_params`),
+    /// any other one ignores them.
+    pub fn with_params(&self, params: Option<Params>) -> &'static dyn Evaluator {
+        match (self, params) {
+            (EvaluatorKind::Weights, Some(params)) => Weights::leak(params),
+            _ => self.evaluator(),
+        }
+    }
+
+    /// Returns whether the evaluator takes parameters
+    /// (see `Params`), i.e. whether it's the weights evaluator
+    pub fn takes_params(&self) -> bool {
+        *self == EvaluatorKind::Weights
+    }
+
+    /// Returns the name used to select the
+    /// evaluator on the command line
+    pub fn key(&self) -> &'static str {
+        match self {
+            EvaluatorKind::Weights => "weights",
+            EvaluatorKind::Windows => "window",
+            EvaluatorKind::Threats => "threat",
+            EvaluatorKind::Zero => "zero",
+            #[cfg(feature = "nn")]
+            EvaluatorKind::Network => "network",
+        }
+    }
+}
+
+impl fmt::Display for EvaluatorKind {
+    /// Display the name of the evaluator (e.g. "Windows")
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.evaluator().name())
+    }
+}
+
+impl FromStr for EvaluatorKind {
+    type Err = String;
+
+    /// Parse an evaluator from its command line name (see `key`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EvaluatorKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.key() == s.trim())
+            .ok_or_else(|| {
+                let keys = EvaluatorKind::ALL.iter().map(|kind| kind.key()).collect::<Vec<_>>().join(", ");
+                format!("unknown evaluator '{}', expected one of: {}", s, keys)
+            })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{board::Cell, eval::score::WIN_SCORE};
+
+    #[test]
+    /// Tests that every evaluator scores a won game
+    /// with the win score, from both perspectives
+    fn test_evaluators_win() {
+        let mut state = State::new();
+        for col in 0 .. 4 {
+            state[(5, col)] = Cell::Player { player: Player::Red };
+        }
+
+        for kind in EvaluatorKind::ALL {
+            let evaluator = kind.evaluator();
+
+            assert_eq!(
+                (evaluator.evaluate(&state, Player::Red), evaluator.evaluate(&state, Player::Yellow)),
+                (WIN_SCORE, -WIN_SCORE),
+                "Expected {} to score red's win, but it didn't",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that evaluators (and their parameters)
+    /// are told apart by their fingerprints
+    fn test_fingerprint() {
+        let tuned = Weights(Params { position: 0.1, ..Params::DEFAULT });
+        let fingerprints = [Weights::DEFAULT.fingerprint(), tuned.fingerprint(), Windows.fingerprint(), Threats.fingerprint(), Zero.fingerprint()];
+
+        for (i, fingerprint) in fingerprints.iter().enumerate() {
+            assert!(
+                !fingerprints[.. i].contains(fingerprint),
+                "Expected distinct fingerprints, got {:?}",
+                fingerprints
+            );
+        }
+
+        assert_eq!(
+            Weights(Params::DEFAULT).fingerprint(), fingerprints[0],
+            "Expected the same parameters to have the same fingerprint, but they didn't",
+        );
+    }
+
+    #[test]
+    /// Tests that the zero evaluator scores undecided games as draws
+    fn test_zero() {
+        let mut state = State::new();
+        state.drop(3, Player::Red);
+
+        assert_eq!(
+            Zero.evaluate(&state, Player::Red), 0.0,
+            "Expected an undecided game to be scored 0, got {}",
+            Zero.evaluate(&state, Player::Red)
+        );
+    }
+
+    #[test]
+    fn test_evaluator_kind_from_str() {
+        for kind in EvaluatorKind::ALL {
+            assert_eq!(
+                kind.key().parse::<EvaluatorKind>(), Ok(*kind),
+                "Expected {} to be parsed as {:?}, but it wasn't",
+                kind.key(), kind,
+            );
+        }
+
+        assert!(
+            "bogus".parse::<EvaluatorKind>().is_err(),
+            "Expected an unknown evaluator to be rejected, but it wasn't",
+        );
+    }
+}
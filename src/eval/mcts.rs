@@ -0,0 +1,531 @@
+use std::{sync::atomic::{AtomicBool, Ordering}, time::{Duration, Instant}};
+
+use crate::board::{Move, Player, State};
+
+use super::{random::Rng, score::Score, search::SearchResult, engine::Engine, difficulty::Difficulty};
+
+/// The number of iterations between two progress reports
+const REPORT_INTERVAL: usize = 1000;
+
+/// The score (in pieces) reported for a move winning every playout,
+/// so that win rates can be compared to the scores of other engines
+const RATE_SCALE: f32 = 20.0;
+
+/// The default maximum number of nodes of the tree (each
+/// holding a position, i.e. a few hundred bytes)
+pub const MAX_NODES: usize = 500_000;
+
+/// How the game is played out from a new node of the tree
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Playout {
+    /// Uniformly random moves
+    Random,
+
+    /// Random moves, except that an immediate win is always
+    /// taken and an immediate loss is blocked if possible
+    Heavy,
+}
+
+/// The configuration of the Monte Carlo tree search
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MctsConfig {
+    /// the maximum number of iterations (playouts) through the root per
+    /// move, including those kept from previous searches (see `reroot`)
+    pub iterations: usize,
+
+    /// the maximum time to search for, if any
+    pub time: Option<Duration>,
+
+    /// the maximum number of nodes of the tree, once it is reached
+    /// the iterations play out from the leaves without expanding them
+    pub nodes: usize,
+
+    /// the exploration constant of UCT, higher values
+    /// favor less visited moves over better ones
+    pub exploration: f32,
+
+    pub playout: Playout,
+}
+
+impl MctsConfig {
+    /// Returns a configuration running the given number
+    /// of iterations with heavy playouts, regardless of time
+    pub fn iterations(iterations: usize) -> MctsConfig {
+        MctsConfig { iterations, time: None, nodes: MAX_NODES, exploration: std::f32::consts::SQRT_2, playout: Playout::Heavy }
+    }
+
+    /// Returns this configuration, additionally limiting the time to search for
+    pub fn with_time(self, time: Duration) -> MctsConfig {
+        MctsConfig { time: Some(time), ..self }
+    }
+
+    /// Returns this configuration with the given maximum number of nodes
+    pub fn with_nodes(self, nodes: usize) -> MctsConfig {
+        MctsConfig { nodes, ..self }
+    }
+
+    /// Returns this configuration with the given playouts
+    pub fn with_playout(self, playout: Playout) -> MctsConfig {
+        MctsConfig { playout, ..self }
+    }
+}
+
+/// A node of the search tree, i.e. a position
+/// and the statistics of the playouts through it
+struct Node {
+    state: State,
+
+    /// the move leading to the node (`None` for the root)
+    /// and the player who made it
+    mv: Option<(Move, Player)>,
+
+    parent: Option<usize>,
+    children: Vec<usize>,
+
+    /// the legal moves not yet added as children
+    untried: Vec<Move>,
+
+    visits: u32,
+
+    /// the sum of the playout rewards of the player who made the
+    /// move leading to the node (1 for a win, 0.5 for a draw)
+    reward: f32,
+}
+
+impl Node {
+    fn new(state: State, mv: Option<(Move, Player)>, parent: Option<usize>) -> Node {
+        let untried = match state.is_terminal() {
+            true => vec![],
+            false => state.get_valid(),
+        };
+
+        Node { state, mv, parent, children: vec![], untried, visits: 0, reward: 0.0 }
+    }
+
+    /// Returns the share of playouts through the node
+    /// won by the player who made its move
+    fn rate(&self) -> f32 {
+        match self.visits {
+            0 => 0.0,
+            visits => self.reward / visits as f32,
+        }
+    }
+}
+
+/// A Monte Carlo tree search engine using UCT. The tree is kept
+/// between moves, so that the playouts through the positions
+/// actually reached are not wasted.
+pub struct Mcts {
+    pub config: MctsConfig,
+    pub rng: Rng,
+
+    /// the nodes of the tree, the root being the first one
+    tree: Vec<Node>,
+}
+
+impl Mcts {
+    /// Returns an engine with the given configuration and an empty tree
+    pub fn new(config: MctsConfig) -> Mcts {
+        Mcts { config, rng: Rng::from_time(), tree: vec![] }
+    }
+
+    /// Returns this engine with the given random number
+    /// generator (of its playouts), e.g. to replay its search
+    pub fn with_rng(self, rng: Rng) -> Mcts {
+        Mcts { rng, ..self }
+    }
+
+    /// Returns the number of nodes of the tree
+    pub fn tree_size(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns the number of playouts through the root of the tree
+    pub fn root_visits(&self) -> u32 {
+        self.tree.first().map_or(0, |root| root.visits)
+    }
+
+    /// Searches the given position, reporting the progress to the
+    /// given callback every `REPORT_INTERVAL` iterations. The result's
+    /// best move is the most visited one, its depth the length of the
+    /// most visited line of play (the principal variation).
+    pub fn search(&mut self, state: &State, progress: &mut dyn FnMut(&SearchResult)) -> SearchResult {
+        self.search_until(state, &AtomicBool::new(false), progress)
+    }
+
+    /// Searches the given position like `search` until the given flag
+    /// is set (e.g. by another thread), which stops the search like
+    /// running out of time does
+    pub fn search_until(&mut self, state: &State, stop: &AtomicBool, progress: &mut dyn FnMut(&SearchResult)) -> SearchResult {
+        let start = Instant::now();
+        let deadline = self.config.time.map(|time| start + time);
+
+        self.reroot(state);
+
+        // the playouts kept from previous searches (or pondering) count
+        // towards the iterations, so that the reply is found faster
+        let iterations = self.config.iterations.saturating_sub(self.root_visits() as usize);
+
+        let mut result = self.result(0, start);
+        for iteration in 1 ..= iterations {
+            if self.tree[0].untried.is_empty() && self.tree[0].children.is_empty() {
+                break;
+            }
+
+            self.iterate();
+
+            if iteration % REPORT_INTERVAL == 0 || iteration == iterations {
+                result = self.result(iteration as u64, start);
+                progress(&result);
+            }
+
+            if stop.load(Ordering::Relaxed) || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                result = self.result(iteration as u64, start);
+                progress(&result);
+                break;
+            }
+        }
+
+        result
+    }
+
+    /// Makes the node of the given position the root of the tree, keeping
+    /// its subtree. If the position is not found within one round of
+    /// moves from the current root, the tree is started anew.
+    fn reroot(&mut self, state: &State) {
+        let Some(index) = self.find(state) else {
+            self.tree = vec![Node::new(state.clone(), None, None)];
+            return;
+        };
+
+        let mut old = std::mem::take(&mut self.tree).into_iter().map(Some).collect::<Vec<_>>();
+
+        // copy the subtree, parents before their children
+        let mut queue = std::collections::VecDeque::from([(index, None)]);
+        while let Some((old_index, parent)) = queue.pop_front() {
+            let mut node = old[old_index].take().expect("every node has a single parent");
+            let new_index = self.tree.len();
+
+            for &child in &node.children {
+                queue.push_back((child, Some(new_index)));
+            }
+
+            node.parent = parent;
+            node.children.clear();
+            if let Some(parent) = parent {
+                self.tree[parent].children.push(new_index);
+            }
+
+            self.tree.push(node);
+        }
+
+        self.tree[0].mv = None;
+    }
+
+    /// Returns the node of the given position within one
+    /// round of moves (one per player) from the root
+    fn find(&self, state: &State) -> Option<usize> {
+        let mut level = if self.tree.is_empty() { vec![] } else { vec![0] };
+
+        for _ in 0 ..= state.players {
+            if let Some(&index) = level.iter().find(|&&index| self.tree[index].state == *state) {
+                return Some(index);
+            }
+
+            level = level.iter().flat_map(|&index| self.tree[index].children.iter().copied()).collect();
+        }
+
+        None
+    }
+
+    /// Runs a single iteration: selects a node by UCT, expands it
+    /// by one untried move (unless the tree is full), plays the game
+    /// out from there and updates the statistics of every node on
+    /// the way back
+    fn iterate(&mut self) {
+        let mut index = 0;
+        while self.tree[index].untried.is_empty() && !self.tree[index].children.is_empty() {
+            index = self.select(index);
+        }
+
+        if !self.tree[index].untried.is_empty() && self.tree.len() < self.config.nodes {
+            let untried = &mut self.tree[index].untried;
+            let mv = untried.swap_remove(self.rng.below(untried.len()));
+
+            let node = &self.tree[index];
+            let child = Node::new(node.state.played(mv), Some((mv, node.state.player())), Some(index));
+
+            let child_index = self.tree.len();
+            self.tree.push(child);
+            self.tree[index].children.push(child_index);
+            index = child_index;
+        }
+
+        let winner = playout(&self.tree[index].state, self.config.playout, &mut self.rng);
+
+        let mut current = Some(index);
+        while let Some(index) = current {
+            let node = &mut self.tree[index];
+            node.visits += 1;
+            node.reward += match (winner, node.mv) {
+                (None, _) => 0.5,
+                (Some(winner), Some((_, player))) if winner == player => 1.0,
+                _ => 0.0,
+            };
+
+            current = node.parent;
+        }
+    }
+
+    /// Returns the child of the given node with the highest upper
+    /// confidence bound (UCT), i.e. the one most worth exploring
+    fn select(&self, index: usize) -> usize {
+        let parent = &self.tree[index];
+        let log_visits = (parent.visits.max(1) as f32).ln();
+
+        let bound = |child: usize| {
+            let node = &self.tree[child];
+            match node.visits {
+                0 => f32::INFINITY,
+                visits => node.rate() + self.config.exploration * (log_visits / visits as f32).sqrt(),
+            }
+        };
+
+        parent.children
+            .iter()
+            .copied()
+            .max_by(|&a, &b| bound(a).total_cmp(&bound(b)))
+            .expect("the node has children")
+    }
+
+    /// Returns the most visited child of the given node, if any
+    fn most_visited(&self, index: usize) -> Option<usize> {
+        self.tree[index].children.iter().copied().max_by_key(|&child| self.tree[child].visits)
+    }
+
+    /// Returns the current result of the search
+    fn result(&self, iterations: u64, start: Instant) -> SearchResult {
+        let mut pv = vec![];
+        let mut index = 0;
+        while let Some(child) = self.most_visited(index) {
+            if let Some((mv, _)) = self.tree[child].mv {
+                pv.push(mv);
+            }
+            index = child;
+        }
+
+        let score = match self.most_visited(0).map(|child| &self.tree[child]) {
+            Some(node) if node.state.get_winner().is_some_and(|winner| Some(winner) == node.mv.map(|(_, player)| player)) => Score::win_in(1),
+            Some(node) => Score::from_eval((2.0 * node.rate() - 1.0) * RATE_SCALE),
+            None => Score::DRAW,
+        };
+
+        SearchResult {
+            best_move: pv.first().copied(),
+            score,
+            depth: pv.len(),
+            pv,
+            nodes: iterations,
+            tt_probes: 0,
+            tt_hits: 0,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+impl Engine for Mcts {
+    fn name(&self) -> &'static str {
+        "MCTS"
+    }
+
+    fn choose(&mut self, state: &State, progress: &mut dyn FnMut(&SearchResult)) -> Option<Move> {
+        self.search(state, progress).best_move
+    }
+
+    fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.config = difficulty.mcts();
+    }
+
+    /// Searches the opponent's position as long as a move would be
+    /// searched, growing the subtrees of their moves, whose playouts
+    /// count towards the reply once they moved (see `search_until`)
+    fn ponder(&mut self, state: &State, stop: &AtomicBool) -> Option<SearchResult> {
+        if state.is_terminal() {
+            return None;
+        }
+
+        Some(self.search_until(state, stop, &mut |_| ()))
+    }
+}
+
+/// Plays the game out from the given position and returns the
+/// winner (`None` for a draw). As moves can be undone (popped),
+/// a game might never end, so it is called a draw after as many
+/// moves as there are cells on the board.
+fn playout(state: &State, kind: Playout, rng: &mut Rng) -> Option<Player> {
+    let mut state = state.clone();
+
+    for _ in 0 .. state.rows() * state.cols() {
+        if state.is_terminal() {
+            break;
+        }
+
+        let moves = state.get_valid();
+        let mv = match kind {
+            Playout::Random => None,
+            Playout::Heavy => heavy_move(&state, &moves),
+        };
+
+        state.play(mv.unwrap_or_else(|| moves[rng.below(moves.len())]));
+    }
+
+    state.get_winner()
+}
+
+/// Returns the move winning right away, if any, or else
+/// the one preventing the next player from winning with it
+fn heavy_move(state: &State, moves: &[Move]) -> Option<Move> {
+    let rules = state.rules();
+
+    if let Some(&mv) = moves.iter().find(|&&mv| rules.wins_with(state, mv)) {
+        return Some(mv);
+    }
+
+    let mut next = state.clone();
+    next.turn = state.player().next(state.players);
+
+    moves
+        .iter()
+        .copied()
+        .find(|&mv| matches!(mv, Move::Drop(_)) && next.is_legal(mv) && rules.wins_with(&next, mv))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::board::Cell;
+
+    #[test]
+    /// Tests that the engine blocks an immediate loss
+    fn test_mcts_block() {
+        let mut state = State::new();
+        for row in 3 .. 6 {
+            state[(row, 0)] = Cell::Player { player: Player::Yellow };
+        }
+        state[(5, 3)] = Cell::Player { player: Player::Red };
+        state[(5, 4)] = Cell::Player { player: Player::Red };
+        state.turn = Player::Red;
+
+        for playout in [Playout::Random, Playout::Heavy] {
+            let mut mcts = Mcts::new(MctsConfig::iterations(5000).with_playout(playout));
+            mcts.rng = Rng::new(3);
+
+            let result = mcts.search(&state, &mut |_| ());
+
+            assert_eq!(
+                result.best_move, Some(Move::Drop(0)),
+                "Expected the loss to be blocked with {:?} playouts, got {}",
+                playout, result
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that progress is reported and that the
+    /// principal variation starts with the best move
+    fn test_mcts_progress() {
+        let mut mcts = Mcts::new(MctsConfig::iterations(3000));
+        let mut reports = 0;
+
+        let result = mcts.search(&State::new(), &mut |_| reports += 1);
+
+        assert_eq!(
+            (reports, result.nodes), (3, 3000),
+            "Expected 3 reports of 3000 iterations, got {} of {}",
+            reports, result.nodes
+        );
+
+        assert!(
+            !result.pv.is_empty() && result.pv.first() == result.best_move.as_ref(),
+            "Expected the principal variation to start with the best move, got {:?}",
+            result.pv
+        );
+    }
+
+    #[test]
+    /// Tests that the playouts of pondering are kept once
+    /// the opponent moved, and that pondering can be stopped
+    fn test_mcts_ponder() {
+        let mut mcts = Mcts::new(MctsConfig::iterations(2000));
+        let mut state = State::new().played(Move::Drop(3));
+
+        let pondered = mcts.ponder(&state, &AtomicBool::new(false)).map(|result| result.nodes);
+        state.play(Move::Drop(2));
+        let child = mcts.find(&state).map_or(0, |index| mcts.tree[index].visits);
+        let reply = mcts.search(&state, &mut |_| ());
+
+        assert!(
+            pondered == Some(2000) && child > 0 && reply.nodes == 2000 - child as u64 && mcts.root_visits() == 2000,
+            "Expected the reply to take {} fewer playouts after pondering {:?}, got {}",
+            child, pondered, reply.nodes
+        );
+
+        let stopped = mcts.ponder(&State::new().played(Move::Drop(6)), &AtomicBool::new(true)).map(|result| result.nodes);
+
+        assert_eq!(
+            stopped, Some(1),
+            "Expected a stopped search to end right away, got {:?} playouts",
+            stopped
+        );
+    }
+
+    #[test]
+    /// Tests that the tree doesn't grow beyond its maximum
+    /// number of nodes, while the iterations go on
+    fn test_mcts_nodes() {
+        let mut mcts = Mcts::new(MctsConfig::iterations(2000).with_nodes(100));
+        let result = mcts.search(&State::new(), &mut |_| ());
+
+        assert!(
+            mcts.tree_size() == 100 && mcts.root_visits() == 2000 && result.best_move.is_some(),
+            "Expected 2000 playouts through 100 nodes, got {} through {}",
+            mcts.root_visits(), mcts.tree_size()
+        );
+    }
+
+    #[test]
+    /// Tests that the tree of the position actually
+    /// reached is kept and searched further
+    fn test_mcts_reuse() {
+        let mut mcts = Mcts::new(MctsConfig::iterations(2000));
+        let mut state = State::new();
+
+        let mv = mcts.search(&state, &mut |_| ()).best_move.unwrap();
+        state.play(mv);
+        state.play(Move::Drop(0));
+
+        let child = mcts.find(&state).map(|index| mcts.tree[index].visits).unwrap_or(0);
+        let result = mcts.search(&state, &mut |_| ());
+
+        assert_eq!(
+            (mcts.root_visits(), result.nodes), (2000, 2000 - child as u64),
+            "Expected the {} previous playouts to be kept and counted, got {} new ones",
+            child, result.nodes
+        );
+
+        assert!(
+            mcts.tree.iter().skip(1).all(|node| node.parent.is_some_and(|parent| mcts.tree[parent].children.len() <= 7)),
+            "Expected a consistent tree, but it wasn't",
+        );
+
+        // an unrelated position starts a new tree
+        mcts.config.iterations = 10;
+        mcts.search(&State::new().played(Move::Drop(6)), &mut |_| ());
+
+        assert_eq!(
+            mcts.root_visits(), 10,
+            "Expected a new tree, got {} playouts",
+            mcts.root_visits()
+        );
+    }
+}
@@ -1,20 +1,53 @@
-use std::f32;
+use std::{sync::atomic::{AtomicBool, AtomicUsize, Ordering}, thread, time::Instant};
 
-use crate::board::{Player, State, MAXIMIZER, MINIMIZER};
+use crate::{board::{Move, Player, State}, detect::threats::order_moves};
 
-pub type TranspositionTable = std::collections::HashMap<TranspositionKey, TranspositionEntry>;
-pub type TranspositionKey = u64;
+use super::{evaluator::Evaluator, score::Score, search::SearchStats, table::{TranspositionEntry, TranspositionFlag, TranspositionTable}, tablebase::Tablebase};
 
-pub struct TranspositionEntry {
-    pub eval: f32,
-    pub depth: usize,
-    pub flag: TranspositionFlag
+/// The maximum number of threads of a search
+pub const MAX_THREADS: usize = 64;
+
+/// Everything a thread of a search needs besides the position
+#[derive(Clone, Copy)]
+pub struct SearchContext<'a> {
+    /// static evaluation of the leaves
+    pub evaluator: &'a dyn Evaluator,
+
+    /// transposition table (shared by all threads)
+    pub tt: &'a TranspositionTable,
+
+    /// set to abort the search (of a helper thread), the
+    /// result of an aborted search is meaningless
+    pub stop: &'a AtomicBool,
+
+    /// node and transposition table counters (shared by all threads)
+    pub stats: &'a SearchStats,
+
+    /// the search is aborted once this point in time has passed
+    pub deadline: Option<Instant>,
+
+    /// the hashes of the positions of the game so far (see
+    /// `Board::history`), which count as repetitions (PopOut)
+    pub history: &'a [u64],
 }
 
-pub enum TranspositionFlag {
-    EXACT,
-    LOWERBOUND,
-    UPPERBOUND,
+impl SearchContext<'_> {
+    /// Returns whether the search was aborted (see `stop`, `deadline`)
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed) || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Returns the search path of the given root, i.e. the
+    /// hashes of the game's positions up to and including it
+    fn root_path(&self, root: &State) -> Vec<u64> {
+        let hash = root.hash_value();
+        let mut path = self.history.to_vec();
+        if path.last() != Some(&hash) {
+            path.push(hash);
+        }
+
+        path
+    }
 }
 
 impl State {
@@ -22,39 +55,98 @@ impl State {
         &self,
         depth: usize,
 
-        // transposition table
-        tt: &mut TranspositionTable,
+        // evaluator, transposition table, ...
+        ctx: &SearchContext,
+
+        // hashes of the positions on the current search path,
+        // used to detect repetitions (PopOut)
+        path: &mut Vec<u64>,
+
+        // the principal variation (best line of play) found
+        // from this position on
+        pv: &mut Vec<Move>,
 
         // alpha-beta pruning
-        mut alpha: f32, // root: -inf
-        mut beta: f32,  // root: +inf
-    ) -> f32 {
+        mut alpha: Score, // root: -inf
+        mut beta: Score,  // root: +inf
+    ) -> Score {
         let alpha_original = alpha;
+        pv.clear();
 
-        let entry = tt.get(&self.hash_value());
-        if let Some(entry) = entry {
-            if entry.depth >= depth {
-                match entry.flag {
-                    TranspositionFlag::EXACT => return entry.eval,
-                    TranspositionFlag::LOWERBOUND => alpha = alpha.max(entry.eval),
-                    TranspositionFlag::UPPERBOUND => beta = beta.min(entry.eval),
-                }
+        if ctx.is_stopped() {
+            return Score::DRAW;
+        }
 
-                if alpha >= beta {
-                    return entry.eval;
-                }
+        ctx.stats.nodes.fetch_add(1, Ordering::Relaxed);
+
+        // Positions can only repeat in variants where pieces
+        // are removed (e.g. PopOut). A repetition is scored as
+        // a draw, as the game would be drawn if it was repeated
+        // often enough
+        let can_repeat = self.rules().can_repeat();
+        if can_repeat {
+            let hash = self.hash_value();
+            if path.contains(&hash) {
+                return Score::DRAW;
             }
         }
 
+        let key = self.canonical_hash();
+        let entry = ctx.tt.get(&key);
+        ctx.stats.tt_probes.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(entry) = entry && entry.depth >= depth {
+            ctx.stats.tt_hits.fetch_add(1, Ordering::Relaxed);
+
+            match entry.flag {
+                TranspositionFlag::EXACT => return entry.eval,
+                TranspositionFlag::LOWERBOUND => alpha = alpha.max(entry.eval),
+                TranspositionFlag::UPPERBOUND => beta = beta.min(entry.eval),
+            }
+
+            if alpha >= beta {
+                return entry.eval;
+            }
+        }
+
+        // positions of the tablebase are scored exactly,
+        // without searching any further
+        if let Some(tablebase) = Tablebase::current() && let Some(score) = tablebase.probe(self, key) {
+            return score;
+        }
+
         if depth == 0 || self.is_terminal() {
-            return self.evaluate();
+            return Score::from_eval(ctx.evaluator.evaluate(self, self.player()));
         }
         
-        let mut value = f32::NEG_INFINITY;
-        for column in self.get_valid() {
-            let child = self.dropped(column, self.player());
-            let score = -child.negamax(depth - 1, tt, -beta, -alpha);   // note: negation + swap (wikipedia: negamax)
-            value = value.max(score);
+        // a move winning right away can't be improved upon,
+        // so there's no need to search any further
+        let mut moves = self.get_valid();
+        if self.players == 2 && let Some(&mv) = moves.iter().find(|&&mv| self.rules().wins_with(self, mv)) {
+            pv.push(mv);
+            return Score::win_in(1);
+        }
+
+        if can_repeat {
+            path.push(self.hash_value());
+        }
+
+        // moves blocking the opponent's threats first
+        order_moves(self, &mut moves);
+
+        let mut value = -Score::INFINITY;
+        let mut child_pv = vec![];
+        for mv in moves {
+            let child = self.played(mv);
+            let score = child.negamax(depth - 1, ctx, path, &mut child_pv, -beta, -alpha).parent();   // note: negation + swap (wikipedia: negamax)
+
+            if score > value {
+                value = score;
+
+                pv.clear();
+                pv.push(mv);
+                pv.append(&mut child_pv);
+            }
 
             alpha = alpha.max(value);
             if alpha >= beta {
@@ -62,11 +154,20 @@ impl State {
             }
         }
 
+        if can_repeat {
+            path.pop();
+        }
+
+        // don't store results of an aborted search
+        if ctx.is_stopped() {
+            return value;
+        }
+
         let flag = if value <= alpha_original { TranspositionFlag::UPPERBOUND }
         else if value >= beta { TranspositionFlag::LOWERBOUND }
         else { TranspositionFlag::EXACT };
 
-        tt.insert(self.hash_value(), TranspositionEntry {
+        ctx.tt.insert(key, TranspositionEntry {
             eval: value,
             depth,
             flag
@@ -75,26 +176,371 @@ impl State {
         value
     }
 
-    pub fn best(
+    /// The "paranoid" search for games with more than two players.
+    /// The root player maximizes their score, while all opponents
+    /// are assumed to form a coalition minimizing it. This reduces
+    /// the game to a two-sided one again, allowing for alpha-beta
+    /// pruning (unlike max^n). Scores are from the root player's
+    /// perspective (see `evaluate_for`).
+    pub fn paranoid(
+        &self,
+        depth: usize,
+        root: Player,
+
+        // evaluator, statistics, ...
+        ctx: &SearchContext,
+
+        // the principal variation (best line of play) found
+        // from this position on
+        pv: &mut Vec<Move>,
+
+        // alpha-beta pruning
+        mut alpha: Score, // root: -inf
+        mut beta: Score,  // root: +inf
+    ) -> Score {
+        pv.clear();
+
+        if ctx.is_stopped() {
+            return Score::DRAW;
+        }
+
+        ctx.stats.nodes.fetch_add(1, Ordering::Relaxed);
+
+        if depth == 0 || self.is_terminal() {
+            return Score::from_eval(ctx.evaluator.evaluate(self, root));
+        }
+
+        let maximizing = self.player() == root;
+        let mut value = if maximizing { -Score::INFINITY } else { Score::INFINITY };
+        let mut child_pv = vec![];
+
+        let mut moves = self.get_valid();
+        order_moves(self, &mut moves);
+
+        for mv in moves {
+            let score = self.played(mv).paranoid(depth - 1, root, ctx, &mut child_pv, alpha, beta).later();
+
+            if (maximizing && score > value) || (!maximizing && score < value) {
+                value = score;
+
+                pv.clear();
+                pv.push(mv);
+                pv.append(&mut child_pv);
+            }
+
+            if maximizing {
+                alpha = alpha.max(value);
+            } else {
+                beta = beta.min(value);
+            }
+
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        value
+    }
+
+    /// Searches the current position to the given depth with the given
+    /// number of threads and returns its score (from the perspective of
+    /// the given player, usually the one to move) and principal variation
+    /// (starting with the best move of the player to move).
+    /// In games of two players, all threads search the whole tree in a
+    /// different order, sharing their results through the transposition
+    /// table ("Lazy SMP"), and the result of the first thread is used.
+    /// In games of more players, the moves are split among the threads.
+    /// A search with a single thread is deterministic. The result of
+    /// an aborted search (see `SearchContext::is_stopped`) is meaningless.
+    pub fn search_depth(
         &self,
         depth: usize,
         player: Player,
-        tt: &mut TranspositionTable,
-    ) -> Option<usize> {
-        let mut best_eval = if player == MAXIMIZER { f32::NEG_INFINITY } else { f32::INFINITY };
-        let mut best_column: Option<usize> = None;
-
-        for column in self.get_valid() {
-            let child = self.dropped(column, player);
-            let score = child.negamax(depth, tt, f32::NEG_INFINITY, f32::INFINITY);  // note: negation
-            if (player == MAXIMIZER && score > best_eval) || (player == MINIMIZER && score < best_eval) {
+        ctx: &SearchContext,
+        threads: usize,
+    ) -> (Score, Vec<Move>) {
+        if self.players > 2 {
+            return self.search_paranoid(depth, player, ctx, threads);
+        }
+
+        let stop = AtomicBool::new(false);
+        let helper_ctx = SearchContext { stop: &stop, ..*ctx };
+
+        let (score, pv) = thread::scope(|scope| {
+            for helper in 1 .. threads {
+                scope.spawn(move || self.search_root(depth, &helper_ctx, helper));
+            }
+
+            let result = self.search_root(depth, ctx, 0);
+            stop.store(true, Ordering::Relaxed);
+
+            result
+        });
+
+        // negamax scores from the perspective of the player to move
+        (if player == self.player() { score } else { -score }, pv)
+    }
+
+    /// Searches all moves of the current player, starting with the
+    /// move at the given offset, and returns the best score and line
+    fn search_root(
+        &self,
+        depth: usize,
+        ctx: &SearchContext,
+        offset: usize,
+    ) -> (Score, Vec<Move>) {
+        let mut moves = self.get_valid();
+        order_moves(self, &mut moves);
+
+        let offset = offset % moves.len().max(1);
+        moves.rotate_left(offset);
+
+        let mut best_eval = -Score::INFINITY;
+        let mut best_pv = vec![];
+        let mut path = ctx.root_path(self);
+        let mut child_pv = vec![];
+
+        for mv in moves {
+            let child = self.played(mv);
+            let score = child.negamax(depth, ctx, &mut path, &mut child_pv, -Score::INFINITY, Score::INFINITY).parent();  // note: negation
+            if best_pv.is_empty() || score > best_eval {
                 best_eval = score;
-                best_column = Some(column);
+
+                best_pv.clear();
+                best_pv.push(mv);
+                best_pv.append(&mut child_pv);
+            }
+        }
+
+        (best_eval, best_pv)
+    }
+
+    /// Searches the current position from the perspective of the given
+    /// player in a game with more than two players (see `paranoid`). If
+    /// an opponent of the player is to move, they minimize the score.
+    fn search_paranoid(
+        &self,
+        depth: usize,
+        player: Player,
+        ctx: &SearchContext,
+        threads: usize,
+    ) -> (Score, Vec<Move>) {
+        let moves = self.get_valid();
+        let maximizing = self.player() == player;
+
+        if threads <= 1 {
+            let mut best_eval = if maximizing { -Score::INFINITY } else { Score::INFINITY };
+            let mut best_pv = vec![];
+            let mut child_pv = vec![];
+
+            for mv in moves {
+                let (alpha, beta) = if maximizing { (best_eval, Score::INFINITY) } else { (-Score::INFINITY, best_eval) };
+                let score = self.played(mv).paranoid(depth, player, ctx, &mut child_pv, alpha, beta).later();
+
+                if best_pv.is_empty() || (maximizing && score > best_eval) || (!maximizing && score < best_eval) {
+                    best_eval = score;
+
+                    best_pv.clear();
+                    best_pv.push(mv);
+                    best_pv.append(&mut child_pv);
+                }
+            }
+
+            return (best_eval, best_pv);
+        }
+
+        // all moves are searched with a full window, so the result
+        // doesn't depend on which thread searched which move
+        let results = split_moves(&moves, threads, |mv| self.search_move(mv, depth, player, ctx));
+
+        // the first of several equally good moves, like above
+        results
+            .into_iter()
+            .enumerate()
+            .max_by_key(|&(index, (score, _))| (if maximizing { score } else { -score }, std::cmp::Reverse(index)))
+            .map_or((-Score::INFINITY, vec![]), |(_, result)| result)
+    }
+
+    /// Searches the given move of the given player (to move) with a
+    /// full window and returns its score and principal variation
+    /// (starting with the move itself)
+    pub fn search_move(
+        &self,
+        mv: Move,
+        depth: usize,
+        player: Player,
+        ctx: &SearchContext,
+    ) -> (Score, Vec<Move>) {
+        let child = self.played(mv);
+        let mut pv = vec![];
+
+        let score = if self.players > 2 {
+            child.paranoid(depth, player, ctx, &mut pv, -Score::INFINITY, Score::INFINITY).later()
+        } else {
+            let mut path = ctx.root_path(self);
+            child.negamax(depth, ctx, &mut path, &mut pv, -Score::INFINITY, Score::INFINITY).parent()
+        };
+
+        pv.insert(0, mv);
+        (score, pv)
+    }
+}
+
+/// Calls the given function for every move, split among the given
+/// number of threads (every thread takes the next move that wasn't
+/// taken yet), and returns the results in the order of the moves
+pub fn split_moves<T: Send>(moves: &[Move], threads: usize, f: impl Fn(Move) -> T + Sync) -> Vec<T> {
+    let next = AtomicUsize::new(0);
+
+    let mut results: Vec<(usize, T)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0 .. threads.max(1)).map(|_| scope.spawn(|| {
+            let mut results = vec![];
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(&mv) = moves.get(index) else {
+                    break results;
+                };
+
+                results.push((index, f(mv)));
             }
+        })).collect();
+
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    });
+
+    results.sort_by_key(|&(index, _)| index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{board::{Cell, Move, Player, State}, eval::{evaluator::{EvaluatorKind, Windows}, search::SearchLimits}};
+    use super::*;
+
+    #[test]
+    /// Tests that the paranoid search takes an immediate
+    /// win in a three-player game
+    fn test_best_paranoid_win() {
+        let mut state = State::with_size(7, 9).with_players(3);
+
+        for col in 1 .. 4 {
+            state[(6, col)] = Cell::Player { player: Player::Green };
         }
+        for col in 5 .. 8 {
+            state[(6, col)] = Cell::Player { player: Player::Red };
+        }
+        state.turn = Player::Green;
+
+        let best = state.best(SearchLimits::depth(2), Player::Green, &Windows, &TranspositionTable::new(), 1).best_move;
 
-        println!();
+        assert!(
+            matches!(best, Some(Move::Drop(0)) | Some(Move::Drop(4))),
+            "Expected Green to complete its row, got {:?}",
+            best
+        );
+    }
+
+    #[test]
+    /// Tests that an immediate win is preferred over a slower one
+    fn test_best_quick_win() {
+        let mut state = State::new();
+
+        // red can win right away in column 3, or later
+        // on (after blocking) in column 6
+        for col in 0 .. 3 {
+            state.drop(col, Player::Red);
+            state.drop(6, Player::Yellow);
+        }
+        state.drop(6, Player::Red);
+        state.drop(5, Player::Yellow);
+        state.turn = Player::Red;
+
+        let best = state.best(SearchLimits::depth(4), Player::Red, &Windows, &TranspositionTable::new(), 1).best_move;
+
+        assert_eq!(
+            best, Some(Move::Drop(3)),
+            "Expected red to win right away, got {:?}",
+            best
+        );
+    }
+
+    #[test]
+    /// Tests that all evaluators, including the zero
+    /// evaluator, find an immediate win
+    fn test_best_evaluators() {
+        let mut state = State::new();
+        for row in 3 .. 6 {
+            state[(row, 2)] = Cell::Player { player: Player::Yellow };
+            state[(row, 5)] = Cell::Player { player: Player::Red };
+        }
+        state.turn = Player::Red;
 
-        best_column
+        for kind in EvaluatorKind::ALL {
+            let best = state.best(SearchLimits::depth(2), Player::Red, kind.evaluator(), &TranspositionTable::new(), 1).best_move;
+
+            assert_eq!(
+                best, Some(Move::Drop(5)),
+                "Expected {} to find the win, got {:?}",
+                kind, best
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that decided games are scored by their distance
+    fn test_negamax_distance() {
+        let mut state = State::new();
+        for col in 2 .. 5 {
+            state[(5, col)] = Cell::Player { player: Player::Red };
+        }
+
+        let (tt, stop, stats) = (TranspositionTable::new(), AtomicBool::new(false), SearchStats::default());
+        let ctx = SearchContext { evaluator: &Windows, tt: &tt, stop: &stop, stats: &stats, deadline: None, history: &[] };
+
+        // yellow can only block one end of _ _ R R R _ _
+        state.turn = Player::Yellow;
+        let score = state.negamax(3, &ctx, &mut vec![], &mut vec![], -Score::INFINITY, Score::INFINITY);
+
+        assert_eq!(
+            score, Score::loss_in(2),
+            "Expected yellow to lose in two plies, got {}",
+            score
+        );
+
+        state.turn = Player::Red;
+        let score = state.negamax(3, &ctx, &mut vec![], &mut vec![], -Score::INFINITY, Score::INFINITY);
+
+        assert_eq!(
+            score, Score::win_in(1),
+            "Expected red to win right away, got {}",
+            score
+        );
+    }
+
+    #[test]
+    /// Tests that a parallel search finds the same moves
+    /// as a single-threaded one, for two and three players
+    fn test_best_threads() {
+        let mut two = State::new();
+        for col in [2, 3] {
+            two.drop(col, Player::Red);
+            two.drop(col, Player::Yellow);
+        }
+
+        let mut three = State::with_size(7, 9).with_players(3);
+        for col in [3, 4, 5] {
+            three.play(Move::Drop(col));
+        }
+
+        for state in [two, three] {
+            let single = state.best(SearchLimits::depth(3), state.player(), &Windows, &TranspositionTable::new(), 1).best_move;
+            let parallel = state.best(SearchLimits::depth(3), state.player(), &Windows, &TranspositionTable::new(), 4).best_move;
+
+            assert_eq!(
+                single, parallel,
+                "Expected the parallel search to find {:?}, got {:?}",
+                single, parallel
+            );
+        }
     }
 }
@@ -1,2 +1,14 @@
+pub mod difficulty;
+pub mod engine;
+pub mod evaluator;
 pub mod score;
+pub mod mcts;
 pub mod minimax;
+#[cfg(feature = "nn")]
+pub mod nn;
+pub mod params;
+pub mod random;
+pub mod search;
+pub mod table;
+pub mod tablebase;
+pub mod window;
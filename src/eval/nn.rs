@@ -0,0 +1,325 @@
+use std::{fmt, fs, str::FromStr, sync::OnceLock};
+
+use crate::board::{Player, State, MAX_DIMENSION, MIN_DIMENSION};
+
+use super::{evaluator::{Evaluator, Windows}, random::{stable_hash, Rng}, score::MAX_HEURISTIC};
+
+/// The first line of every weights file, identifying its format
+pub const FORMAT: &str = "confour-nn 1";
+
+/// The default number of hidden units
+pub const HIDDEN: usize = 32;
+
+/// The largest number of hidden units of a network
+pub const MAX_HIDDEN: usize = 1024;
+
+/// The network installed at runtime, if any (see `Network::install`)
+static INSTALLED: OnceLock<Network> = OnceLock::new();
+
+/// A small multilayer perceptron evaluating positions of one board
+/// size: the input are two planes of the board (the pieces of the
+/// player and the ones of their opponents), followed by a single
+/// hidden layer (ReLU) and a single output (tanh), which is the
+/// expected result for the player (1 for a win, -1 for a loss).
+#[derive(Clone, PartialEq, Debug)]
+pub struct Network {
+    pub rows: usize,
+    pub cols: usize,
+    pub hidden: usize,
+
+    /// the weights (hidden x inputs, row by row) and biases of the hidden layer
+    pub w1: Vec<f32>,
+    pub b1: Vec<f32>,
+
+    /// the weights and bias of the output
+    pub w2: Vec<f32>,
+    pub b2: f32,
+}
+
+impl Network {
+    /// Returns a network for boards of the given size with
+    /// the given number of hidden units and random weights
+    pub fn new(rows: usize, cols: usize, hidden: usize, rng: &mut Rng) -> Network {
+        let inputs = 2 * rows * cols;
+        let mut random = |scale: f32| (rng.next_f32() * 2.0 - 1.0) * scale;
+
+        let w1 = (0 .. hidden * inputs).map(|_| random((2.0 / inputs as f32).sqrt())).collect();
+        let w2 = (0 .. hidden).map(|_| random((1.0 / hidden as f32).sqrt())).collect();
+
+        Network { rows, cols, hidden, w1, b1: vec![0.0; hidden], w2, b2: 0.0 }
+    }
+
+    /// Returns the number of inputs of the network
+    pub fn inputs(&self) -> usize {
+        2 * self.rows * self.cols
+    }
+
+    /// Returns the network installed at runtime, if any
+    pub fn current() -> Option<&'static Network> {
+        INSTALLED.get()
+    }
+
+    /// Installs the network used by the `Neural` evaluator from now
+    /// on. A network can only be installed once (e.g. at startup).
+    pub fn install(self) -> Result<(), String> {
+        INSTALLED.set(self).map_err(|_| "a network is already installed".to_string())
+    }
+
+    /// Reads the network from the given weights file
+    pub fn load(path: &str) -> Result<Network, String> {
+        fs::read_to_string(path)
+            .map_err(|err| format!("cannot read '{}': {}", path, err))?
+            .parse()
+    }
+
+    /// Writes the network to the given weights file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|err| format!("cannot write '{}': {}", path, err))
+    }
+
+    /// Returns whether the network evaluates boards of the given state's size
+    pub fn fits(&self, state: &State) -> bool {
+        (self.rows, self.cols) == (state.rows(), state.cols())
+    }
+
+    /// Returns the input planes of the given state from
+    /// the perspective of the given player
+    pub fn planes(state: &State, player: Player) -> Vec<f32> {
+        let cells = state.rows() * state.cols();
+        let mut planes = vec![0.0; 2 * cells];
+
+        for (i, cell) in state.data.iter().flatten().enumerate() {
+            match cell.player() {
+                Some(owner) if owner == player => planes[i] = 1.0,
+                Some(_) => planes[cells + i] = 1.0,
+                None => (),
+            }
+        }
+
+        planes
+    }
+
+    /// Returns the activations of the hidden layer and
+    /// the output of the network for the given input
+    fn forward(&self, input: &[f32]) -> (Vec<f32>, f32) {
+        let inputs = self.inputs();
+
+        let hidden = (0 .. self.hidden)
+            .map(|h| {
+                let weights = &self.w1[h * inputs .. (h + 1) * inputs];
+                let sum = self.b1[h] + weights.iter().zip(input).map(|(w, x)| w * x).sum::<f32>();
+                sum.max(0.0)
+            })
+            .collect::<Vec<_>>();
+
+        let output = (self.b2 + hidden.iter().zip(&self.w2).map(|(a, w)| a * w).sum::<f32>()).tanh();
+
+        (hidden, output)
+    }
+
+    /// Returns the expected result (between -1 and 1) of
+    /// the given state for the given player
+    pub fn predict(&self, state: &State, player: Player) -> f32 {
+        self.forward(&Network::planes(state, player)).1
+    }
+
+    /// Trains the network on a single input and its target (between
+    /// -1 and 1) with one step of stochastic gradient descent of
+    /// the squared error. Returns the error before the step.
+    pub fn train(&mut self, input: &[f32], target: f32, rate: f32) -> f32 {
+        let inputs = self.inputs();
+        let (hidden, output) = self.forward(input);
+
+        let error = output - target;
+        let delta = error * (1.0 - output * output);
+
+        for (h, &activation) in hidden.iter().enumerate() {
+            // inactive units (ReLU) do not pass on any gradient
+            let delta_h = if activation > 0.0 { delta * self.w2[h] } else { 0.0 };
+
+            self.w2[h] -= rate * delta * activation;
+
+            if delta_h != 0.0 {
+                self.b1[h] -= rate * delta_h;
+                for (w, x) in self.w1[h * inputs .. (h + 1) * inputs].iter_mut().zip(input) {
+                    *w -= rate * delta_h * x;
+                }
+            }
+        }
+        self.b2 -= rate * delta;
+
+        error * error
+    }
+}
+
+impl fmt::Display for Network {
+    /// Display the network in the format of a weights file: the
+    /// format line, the dimensions ("<rows> <cols> <hidden>"), then
+    /// the hidden weights (one line per unit), the hidden biases,
+    /// the output weights and the output bias
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = |values: &[f32]| values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(" ");
+
+        writeln!(f, "{}", FORMAT)?;
+        writeln!(f, "{} {} {}", self.rows, self.cols, self.hidden)?;
+        for weights in self.w1.chunks(self.inputs().max(1)) {
+            writeln!(f, "{}", line(weights))?;
+        }
+        writeln!(f, "{}", line(&self.b1))?;
+        writeln!(f, "{}", line(&self.w2))?;
+        writeln!(f, "{}", self.b2)
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    /// Parse a network from a weights file (see `Display`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+
+        if lines.next() != Some(FORMAT) {
+            return Err(format!("not a weights file, expected '{}'", FORMAT));
+        }
+
+        let mut numbers = |count: usize| -> Result<Vec<f32>, String> {
+            let line = lines.next().ok_or("unexpected end of the weights file")?;
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>().map_err(|_| format!("invalid number '{}'", value)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match values.len() == count {
+                true => Ok(values),
+                false => Err(format!("expected {} numbers, got {}", count, values.len())),
+            }
+        };
+
+        let dimensions = numbers(3)?;
+        let (rows, cols, hidden) = (dimensions[0] as usize, dimensions[1] as usize, dimensions[2] as usize);
+
+        let board = MIN_DIMENSION ..= MAX_DIMENSION;
+        if dimensions.iter().any(|dimension| dimension.fract() != 0.0) || !board.contains(&rows) || !board.contains(&cols) || !(1 ..= MAX_HIDDEN).contains(&hidden) {
+            return Err(format!(
+                "invalid dimensions {} x {} x {}, expected {} - {} rows and columns and 1 - {} hidden units",
+                dimensions[0], dimensions[1], dimensions[2], MIN_DIMENSION, MAX_DIMENSION, MAX_HIDDEN
+            ));
+        }
+
+        let inputs = 2 * rows * cols;
+        let mut w1 = Vec::with_capacity(hidden.checked_mul(inputs).ok_or("too many weights")?);
+        for _ in 0 .. hidden {
+            w1.extend(numbers(inputs)?);
+        }
+        let (b1, w2, b2) = (numbers(hidden)?, numbers(hidden)?, numbers(1)?[0]);
+
+        Ok(Network { rows, cols, hidden, w1, b1, w2, b2 })
+    }
+}
+
+impl Evaluator for Network {
+    fn name(&self) -> &'static str {
+        "Network"
+    }
+
+    /// Scores positions with the network's expected result, falling
+    /// back to `Windows` if the network doesn't fit the board
+    fn evaluate(&self, state: &State, player: Player) -> f32 {
+        if let Some(outcome) = state.outcome_for(player) {
+            return outcome;
+        }
+
+        match self.fits(state) {
+            true => self.predict(state, player) * MAX_HEURISTIC,
+            false => Windows.evaluate(state, player),
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        let dimensions = [self.rows, self.cols, self.hidden].map(|dimension| dimension as u64);
+        let weights = self.w1.iter().chain(&self.b1).chain(&self.w2).chain([&self.b2]).map(|weight| weight.to_bits() as u64);
+
+        stable_hash(self.name().bytes().map(u64::from).chain(dimensions).chain(weights))
+    }
+}
+
+/// Scores positions with the installed network (see `Network::install`),
+/// falling back to `Windows` if there is none or it doesn't fit the board
+pub struct Neural;
+
+impl Evaluator for Neural {
+    fn name(&self) -> &'static str {
+        "Network"
+    }
+
+    fn evaluate(&self, state: &State, player: Player) -> f32 {
+        match Network::current() {
+            Some(network) => network.evaluate(state, player),
+            None => Windows.evaluate(state, player),
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        match Network::current() {
+            Some(network) => network.fingerprint(),
+            None => Windows.fingerprint(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::board::Move;
+
+    #[test]
+    /// Tests that a network is written to and read
+    /// from a weights file without changing it
+    fn test_weights_file() {
+        let network = Network::new(4, 5, 3, &mut Rng::new(1));
+        let parsed = network.to_string().parse::<Network>();
+
+        assert_eq!(
+            parsed, Ok(network),
+            "Expected the network to be read back, got {:?}",
+            parsed
+        );
+
+        assert!(
+            "confour-nn 1\n4 5 3\n1 2".parse::<Network>().is_err() && "bogus".parse::<Network>().is_err(),
+            "Expected invalid weights files to be rejected, but they weren't",
+        );
+
+        for dimensions in ["1000000 1000000 1000000", "0 0 3", "4 5 0", "11 5 3", "4 5 2000", "4.5 5 3", "-4 5 3"] {
+            let parsed = format!("confour-nn 1\n{}\n", dimensions).parse::<Network>();
+
+            assert!(
+                parsed.is_err(),
+                "Expected the dimensions {} to be rejected, got {:?}",
+                dimensions, parsed.map(|network| network.inputs())
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that training fits the network to a few positions
+    fn test_train() {
+        let mut network = Network::new(6, 7, 8, &mut Rng::new(2));
+        let (a, b) = (State::new().played(Move::Drop(3)), State::new().played(Move::Drop(0)));
+        let samples = [(Network::planes(&a, Player::Red), 0.8), (Network::planes(&b, Player::Red), -0.8)];
+
+        for _ in 0 .. 500 {
+            for (input, target) in &samples {
+                network.train(input, *target, 0.05);
+            }
+        }
+
+        let (a, b) = (network.predict(&a, Player::Red), network.predict(&b, Player::Red));
+
+        assert!(
+            (a - 0.8).abs() < 0.1 && (b + 0.8).abs() < 0.1,
+            "Expected the targets to be fitted, got {} and {}",
+            a, b
+        );
+    }
+}
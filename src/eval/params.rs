@@ -0,0 +1,242 @@
+use std::{fmt, fs, str::FromStr};
+
+use crate::{board::{Player, State}, detect::{sequences_all, MAX_WIN_LENGTH}};
+
+use super::score::position_weight;
+
+/// The number of streak lengths scored separately, by the number
+/// of pieces they are missing to win (from 1 to `MAX_WIN_LENGTH - 1`)
+pub const STREAKS: usize = MAX_WIN_LENGTH - 1;
+
+/// The number of parameters (see `Params::values`)
+pub const PARAMS: usize = STREAKS + 2;
+
+/// The parameters of the sequence evaluation (see `State::evaluate_for`),
+/// i.e. the weights of its features, as used by an instance of the
+/// `Weights` evaluator. Tuned parameters can be written to and read
+/// from a parameter file (see `Display` and `FromStr`).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Params {
+    /// the weight of the positional weights of the cells of a streak
+    pub position: f32,
+
+    /// the score of a streak missing 1, 2, ... pieces to win
+    pub streaks: [f32; STREAKS],
+
+    /// the score of a collected piece (Pop 10)
+    pub captured: f32,
+}
+
+impl Params {
+    /// The hand-chosen parameters: positional weights scaled to
+    /// the hand-made 7x6 table they replaced (a fifth of its weights,
+    /// which add up to 89 instead of 276), streaks doubling in value
+    /// with every piece (such that a streak of two in "Connect-3" is
+    /// worth as much as a streak of three in standard connect four)
+    pub const DEFAULT: Params = Params {
+        position: 0.065,
+        streaks: [8.0, 4.0, 2.0, 1.0, 0.5],
+        captured: 10.0,
+    };
+
+    /// Reads the parameters from the given parameter file
+    pub fn load(path: &str) -> Result<Params, String> {
+        fs::read_to_string(path)
+            .map_err(|err| format!("cannot read '{}': {}", path, err))?
+            .parse()
+    }
+
+    /// Writes the parameters to the given parameter file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|err| format!("cannot write '{}': {}", path, err))
+    }
+
+    /// Returns all parameters in a fixed order
+    /// (position, streaks, captured), e.g. for tuning
+    pub fn values(&self) -> [f32; PARAMS] {
+        let mut values = [0.0; PARAMS];
+        values[0] = self.position;
+        values[1 ..= STREAKS].copy_from_slice(&self.streaks);
+        values[PARAMS - 1] = self.captured;
+        values
+    }
+
+    /// Returns the parameters of the given values (see `values`)
+    pub fn from_values(values: [f32; PARAMS]) -> Params {
+        let mut streaks = [0.0; STREAKS];
+        streaks.copy_from_slice(&values[1 ..= STREAKS]);
+
+        Params { position: values[0], streaks, captured: values[PARAMS - 1] }
+    }
+
+    /// Returns the score of the given features
+    pub fn evaluate(&self, features: &Features) -> f32 {
+        self.values().iter().zip(features.values()).map(|(param, feature)| param * feature).sum()
+    }
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params::DEFAULT
+    }
+}
+
+impl fmt::Display for Params {
+    /// Display the parameters in the format of
+    /// a parameter file, one parameter per line, e.g.
+    ///
+    /// ```text
+    /// position = 0.065
+    /// streaks = 8 4 2 1 0.5
+    /// captured = 10
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let streaks = self.streaks.map(|streak| streak.to_string()).join(" ");
+
+        writeln!(f, "# evaluation parameters (see `Params`)")?;
+        writeln!(f, "position = {}", self.position)?;
+        writeln!(f, "streaks = {}", streaks)?;
+        writeln!(f, "captured = {}", self.captured)
+    }
+}
+
+impl FromStr for Params {
+    type Err = String;
+
+    /// Parse the parameters from a parameter file (see `Display`),
+    /// skipping empty lines and comments starting with '#'. Missing
+    /// parameters keep their default values.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut params = Params::default();
+
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let (key, value) = line.split_once('=').ok_or(format!("invalid parameter line '{}'", line))?;
+            let numbers = value
+                .split_whitespace()
+                .map(|number| number.parse::<f32>().map_err(|_| format!("invalid number '{}'", number)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match (key.trim(), numbers.as_slice()) {
+                ("position", &[position]) => params.position = position,
+                ("captured", &[captured]) => params.captured = captured,
+                ("streaks", streaks) if streaks.len() == STREAKS => params.streaks.copy_from_slice(streaks),
+                _ => return Err(format!("invalid parameter line '{}'", line)),
+            }
+        }
+
+        Ok(params)
+    }
+}
+
+/// The features of a position scored by the sequence evaluation, i.e.
+/// what the parameters are multiplied with (see `Params::evaluate`)
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Features {
+    /// the sum of the positional weights of the cells of all streaks
+    pub position: f32,
+
+    /// the number of streaks missing 1, 2, ... pieces to win
+    pub streaks: [f32; STREAKS],
+
+    /// the number of collected pieces (Pop 10)
+    pub captured: f32,
+}
+
+impl Features {
+    /// Returns all features in the order of `Params::values`
+    pub fn values(&self) -> [f32; PARAMS] {
+        let mut values = [0.0; PARAMS];
+        values[0] = self.position;
+        values[1 ..= STREAKS].copy_from_slice(&self.streaks);
+        values[PARAMS - 1] = self.captured;
+        values
+    }
+
+    /// Returns the difference of these features and the given ones,
+    /// e.g. of a player's and their opponent's features
+    pub fn minus(&self, other: &Features) -> Features {
+        let mut streaks = self.streaks;
+        for (streak, other) in streaks.iter_mut().zip(other.streaks) {
+            *streak -= other;
+        }
+
+        Features { position: self.position - other.position, streaks, captured: self.captured - other.captured }
+    }
+}
+
+impl State {
+    /// Returns the features of the given player's sequences
+    /// (and collected pieces) scored by the evaluation
+    pub fn features(&self, player: Player) -> Features {
+        let (rows, cols) = (self.rows(), self.cols());
+        let mut features = Features { captured: self.captured[player.index()] as f32, ..Features::default() };
+
+        for sequence in sequences_all(self, player) {
+            for cell in &sequence {
+                features.position += position_weight(rows, cols, self.win_length, self.cylinder, cell.0, cell.1) as f32;
+            }
+
+            let missing = self.win_length.saturating_sub(sequence.len()).clamp(1, STREAKS);
+            features.streaks[missing - 1] += 1.0;
+        }
+
+        features
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::board::{Cell, Move};
+
+    #[test]
+    /// Tests that parameters are written to and read
+    /// from a parameter file without changing them
+    fn test_params_file() {
+        let params = Params { position: 0.35, streaks: [9.5, 3.0, 1.25, 0.0, -0.5], captured: 12.0 };
+        let parsed = params.to_string().parse::<Params>();
+
+        assert_eq!(
+            parsed, Ok(params),
+            "Expected the parameters to be read back, got {:?}",
+            parsed
+        );
+
+        for invalid in ["position 1", "position = x", "streaks = 1 2", "bogus = 1"] {
+            assert!(
+                invalid.parse::<Params>().is_err(),
+                "Expected {:?} to be rejected, but it wasn't",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that the default parameters score streaks
+    /// the same way on boards of any win length
+    fn test_params_default() {
+        let mut state = State::new().with_win_length(3);
+        state.play(Move::Drop(3));
+        state.play(Move::Drop(0));
+        state.play(Move::Drop(4));
+
+        let features = state.features(Player::Red);
+
+        assert_eq!(
+            features.streaks, [1.0, 0.0, 0.0, 0.0, 0.0],
+            "Expected a single streak missing one piece, got {:?}",
+            features.streaks
+        );
+
+        let mut standard = State::new();
+        for col in 2 .. 5 {
+            standard[(5, col)] = Cell::Player { player: Player::Red };
+        }
+
+        assert_eq!(
+            Params::default().evaluate(&Features { position: 0.0, ..state.features(Player::Red) }),
+            Params::default().evaluate(&Features { position: 0.0, ..standard.features(Player::Red) }),
+            "Expected a streak of two in Connect-3 to be worth a streak of three",
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A small, seedable pseudo random number generator ("xorshift64*"),
+/// good enough to make the engine less predictable, but not suitable
+/// for anything security related
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Returns a generator with the given seed,
+    /// always producing the same sequence of numbers
+    pub fn new(seed: u64) -> Rng {
+        // the state must never be zero
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15 | 1)
+    }
+
+    /// Returns a generator seeded from the current time
+    pub fn from_time() -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+
+        Rng::new(nanos)
+    }
+
+    /// Returns the next random number
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a random number in the range [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns a random number in the range [0, n)
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+}
+
+/// The "splitmix64" mixing function, turning any number into a well
+/// distributed one, e.g. to derive fixed random numbers from a seed
+pub const fn splitmix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// Returns a hash of the given numbers that, unlike the hashers of
+/// `std`, stays the same across runs and builds (e.g. to be written
+/// to a file)
+pub fn stable_hash(values: impl IntoIterator<Item = u64>) -> u64 {
+    values.into_iter().fold(0, |hash, value| splitmix(hash ^ value))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests that the same seed results in the same numbers
+    fn test_seed() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        let (a, b) = ((0 .. 8).map(|_| a.next_u64()).collect::<Vec<_>>(), (0 .. 8).map(|_| b.next_u64()).collect::<Vec<_>>());
+
+        assert_eq!(
+            a, b,
+            "Expected the same sequence for the same seed, got {:?} and {:?}",
+            a, b
+        );
+    }
+
+    #[test]
+    /// Tests that the generated numbers are within their ranges
+    fn test_ranges() {
+        let mut rng = Rng::new(7);
+
+        for _ in 0 .. 1000 {
+            let (f, n) = (rng.next_f32(), rng.below(7));
+
+            assert!(
+                (0.0 .. 1.0).contains(&f) && n < 7,
+                "Expected numbers within their ranges, got {} and {}",
+                f, n
+            );
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use std::{fmt, sync::atomic::{AtomicBool, AtomicU64, Ordering}, time::{Duration, Instant}};
+
+use crate::board::{Move, Player, State};
+
+use super::{evaluator::Evaluator, minimax::{split_moves, SearchContext}, score::Score, table::TranspositionTable};
+
+/// Counters of a search, shared by all of its threads
+#[derive(Debug, Default)]
+pub struct SearchStats {
+    /// the number of positions searched
+    pub nodes: AtomicU64,
+
+    /// the number of transposition table lookups
+    pub tt_probes: AtomicU64,
+
+    /// the number of transposition table lookups
+    /// returning an entry of sufficient depth
+    pub tt_hits: AtomicU64,
+}
+
+/// The limits of a search
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SearchLimits {
+    /// the maximum depth to search to
+    pub depth: usize,
+
+    /// the maximum time to search for, if any
+    pub time: Option<Duration>,
+}
+
+impl SearchLimits {
+    /// Returns limits searching to the given depth, regardless of time
+    pub fn depth(depth: usize) -> SearchLimits {
+        SearchLimits { depth, time: None }
+    }
+
+    /// Returns these limits, additionally limiting the time to search for
+    pub fn with_time(self, time: Duration) -> SearchLimits {
+        SearchLimits { time: Some(time), ..self }
+    }
+}
+
+/// The result of a search (or of one of its iterations)
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SearchResult {
+    /// the best move, `None` if there are no legal moves
+    pub best_move: Option<Move>,
+
+    /// the score of the best move (see `Score`)
+    pub score: Score,
+
+    /// the principal variation, i.e. the line of play expected
+    /// if both sides play the best moves, starting with `best_move`
+    pub pv: Vec<Move>,
+
+    /// the depth reached
+    pub depth: usize,
+
+    /// the number of positions searched
+    pub nodes: u64,
+
+    /// the number of transposition table lookups (and hits)
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+
+    /// the time since the search started
+    pub elapsed: Duration,
+}
+
+impl SearchResult {
+    /// Returns the number of positions searched per second
+    pub fn nps(&self) -> u64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => (self.nodes as f64 / secs) as u64,
+            _ => 0,
+        }
+    }
+
+    /// Returns the share of transposition table lookups
+    /// returning a usable entry (between 0 and 1)
+    pub fn tt_hit_rate(&self) -> f64 {
+        match self.tt_probes {
+            0 => 0.0,
+            probes => self.tt_hits as f64 / probes as f64,
+        }
+    }
+
+    /// Returns the principal variation in move notation,
+    /// e.g. "4 4 3" (see `Move`)
+    pub fn pv_notation(&self) -> String {
+        self.pv.iter().map(|mv| mv.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl fmt::Display for SearchResult {
+    /// Display the result as a single line, e.g.
+    /// "depth 6 score +0.35 pv 4 4 3 nodes 12345 nps 98765 tt 41.2% time 125ms"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "depth {} score {} pv {} nodes {} nps {} tt {:.1}% time {}ms",
+            self.depth,
+            self.score,
+            self.pv_notation(),
+            self.nodes,
+            self.nps(),
+            self.tt_hit_rate() * 100.0,
+            self.elapsed.as_millis(),
+        )
+    }
+}
+
+impl State {
+    /// Searches the best move of the player to move, scored from the
+    /// perspective of the given player (usually the one to move), with
+    /// iterative deepening, i.e. searching to depth 1, 2, ... up to
+    /// the given depth. The given callback is called with the result
+    /// of every iteration (e.g. to report progress), the result of the
+    /// last one is returned. Once a game is decided (won or lost), no
+    /// deeper search can change the result, so the search stops early.
+    /// If the time runs out, the iteration running at that time is
+    /// aborted and the result of the previous one is returned. The
+    /// positions of the given game history (see `Board::history`)
+    /// count as repetitions where positions can repeat (PopOut).
+    #[allow(clippy::too_many_arguments)]
+    pub fn search(
+        &self,
+        limits: SearchLimits,
+        player: Player,
+        evaluator: &dyn Evaluator,
+        tt: &TranspositionTable,
+        threads: usize,
+        history: &[u64],
+        progress: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        let (stop, stats) = (AtomicBool::new(false), SearchStats::default());
+        let deadline = limits.time.map(|time| Instant::now() + time);
+        let ctx = SearchContext { evaluator, tt, stop: &stop, stats: &stats, deadline, history };
+
+        self.deepen(limits.depth, player, &ctx, threads, progress)
+    }
+
+    /// Searches the best move of the given player (to move) like
+    /// `search`, without reporting progress, until the given flag
+    /// is set (e.g. by another thread), which stops the search
+    /// like running out of time does
+    #[allow(clippy::too_many_arguments)]
+    pub fn search_until(
+        &self,
+        limits: SearchLimits,
+        player: Player,
+        evaluator: &dyn Evaluator,
+        tt: &TranspositionTable,
+        threads: usize,
+        history: &[u64],
+        stop: &AtomicBool,
+    ) -> SearchResult {
+        let stats = SearchStats::default();
+        let deadline = limits.time.map(|time| Instant::now() + time);
+        let ctx = SearchContext { evaluator, tt, stop, stats: &stats, deadline, history };
+
+        self.deepen(limits.depth, player, &ctx, threads, |_| ())
+    }
+
+    /// Searches with iterative deepening (see `search`)
+    fn deepen(
+        &self,
+        depth: usize,
+        player: Player,
+        ctx: &SearchContext,
+        threads: usize,
+        mut progress: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        let start = Instant::now();
+        let stats = ctx.stats;
+
+        let mut result = SearchResult {
+            best_move: None,
+            score: Score::DRAW,
+            pv: vec![],
+            depth: 0,
+            nodes: 0,
+            tt_probes: 0,
+            tt_hits: 0,
+            elapsed: Duration::ZERO,
+        };
+
+        for iteration in depth.min(1) ..= depth {
+            let (score, pv) = self.search_depth(iteration, player, ctx, threads);
+
+            // the first iteration is kept in any case,
+            // so that there is a move to play
+            if ctx.is_stopped() && result.best_move.is_some() {
+                break;
+            }
+
+            result = SearchResult {
+                best_move: pv.first().copied(),
+                score,
+                pv,
+                depth: iteration,
+                nodes: stats.nodes.load(Ordering::Relaxed),
+                tt_probes: stats.tt_probes.load(Ordering::Relaxed),
+                tt_hits: stats.tt_hits.load(Ordering::Relaxed),
+                elapsed: start.elapsed(),
+            };
+
+            progress(&result);
+
+            if result.score.is_decided() {
+                break;
+            }
+        }
+
+        result
+    }
+
+    /// Searches the best move of the given player (to move),
+    /// without reporting progress or a game history (see `search`)
+    pub fn best(
+        &self,
+        limits: SearchLimits,
+        player: Player,
+        evaluator: &dyn Evaluator,
+        tt: &TranspositionTable,
+        threads: usize,
+    ) -> SearchResult {
+        self.search(limits, player, evaluator, tt, threads, &[], |_| ())
+    }
+
+    /// Searches every legal move of the current player (not just the
+    /// best one) to the given depth, with the moves split among the
+    /// given number of threads. Returns the moves with their scores,
+    /// from the perspective of the current player, in the order of
+    /// `get_valid`. The game history is used like by `search`.
+    pub fn analyze(
+        &self,
+        depth: usize,
+        evaluator: &dyn Evaluator,
+        tt: &TranspositionTable,
+        threads: usize,
+        history: &[u64],
+    ) -> Vec<(Move, Score)> {
+        self.analyze_result(depth, evaluator, tt, threads, history).0
+    }
+
+    /// Searches every legal move like `analyze` and returns the moves
+    /// with their scores, along with the result of the whole search:
+    /// the best move with its score and principal variation, and the
+    /// statistics of the searches of all moves
+    pub fn analyze_result(
+        &self,
+        depth: usize,
+        evaluator: &dyn Evaluator,
+        tt: &TranspositionTable,
+        threads: usize,
+        history: &[u64],
+    ) -> (Vec<(Move, Score)>, SearchResult) {
+        let start = Instant::now();
+        let (stop, stats) = (AtomicBool::new(false), SearchStats::default());
+        let ctx = SearchContext { evaluator, tt, stop: &stop, stats: &stats, deadline: None, history };
+
+        let player = self.player();
+        let moves = self.get_valid();
+        let lines = split_moves(&moves, threads, |mv| self.search_move(mv, depth, player, &ctx));
+
+        // the first of several equally good moves, like `search_depth`
+        let (score, pv) = lines
+            .iter()
+            .enumerate()
+            .max_by_key(|&(index, (score, _))| (*score, std::cmp::Reverse(index)))
+            .map_or((Score::DRAW, vec![]), |(_, line)| line.clone());
+        let result = SearchResult {
+            best_move: pv.first().copied(),
+            score,
+            pv,
+            depth,
+            nodes: stats.nodes.load(Ordering::Relaxed),
+            tt_probes: stats.tt_probes.load(Ordering::Relaxed),
+            tt_hits: stats.tt_hits.load(Ordering::Relaxed),
+            elapsed: start.elapsed(),
+        };
+
+        (moves.into_iter().zip(lines.into_iter().map(|(score, _)| score)).collect(), result)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{board::Cell, eval::evaluator::Windows, rules::Variant};
+
+    #[test]
+    /// Tests that every iteration is reported and that
+    /// the principal variation starts with the best move
+    fn test_search_progress() {
+        let mut state = State::new();
+        state.play(Move::Drop(3));
+
+        let mut depths = vec![];
+        let result = state.search(SearchLimits::depth(3), state.player(), &Windows, &TranspositionTable::new(), 1, &[], |result| depths.push(result.depth));
+
+        assert_eq!(
+            depths, vec![1, 2, 3],
+            "Expected every iteration to be reported, got {:?}",
+            depths
+        );
+
+        assert!(
+            result.pv.len() > 1 && result.pv.first() == result.best_move.as_ref(),
+            "Expected the principal variation to start with the best move, got {:?}",
+            result.pv
+        );
+
+        assert!(
+            result.nodes > 0 && result.tt_probes >= result.tt_hits,
+            "Expected nodes and lookups to be counted, got {}",
+            result
+        );
+    }
+
+    #[test]
+    /// Tests that a decided game stops the search early
+    fn test_search_decided() {
+        let mut state = State::new();
+        for col in 0 .. 3 {
+            state[(5, col)] = Cell::Player { player: Player::Red };
+        }
+
+        let result = state.best(SearchLimits::depth(6), Player::Red, &Windows, &TranspositionTable::new(), 1);
+
+        assert_eq!(
+            (result.best_move, result.score, result.depth), (Some(Move::Drop(3)), Score::win_in(1), 1),
+            "Expected a win in one ply found at depth 1, got {}",
+            result
+        );
+
+        assert_eq!(
+            result.to_string().split_whitespace().take(5).collect::<Vec<_>>(), vec!["depth", "1", "score", "+W1", "pv"],
+            "Expected the result to be displayed with its mate distance, got {}",
+            result
+        );
+    }
+
+    #[test]
+    /// Tests that a move back to a position of the
+    /// game history is scored as a repetition (draw)
+    fn test_analyze_history() {
+        let mut state = State::new().with_variant(Variant::PopOut);
+        state[(5, 3)] = Cell::Player { player: Player::Red };
+        state[(5, 2)] = Cell::Player { player: Player::Yellow };
+
+        let history = [state.played(Move::Drop(4)).hash_value(), state.hash_value()];
+        let score = |history: &[u64]| {
+            let analysis = state.analyze(2, &Windows, &TranspositionTable::new(), 1, history);
+            analysis.into_iter().find(|&(mv, _)| mv == Move::Drop(4)).unwrap().1
+        };
+
+        let (repeated, fresh) = (score(&history), score(&[]));
+
+        assert!(
+            repeated == Score::DRAW && fresh != Score::DRAW,
+            "Expected only the repetition to be a draw, got {} and {}",
+            repeated, fresh
+        );
+    }
+
+    #[test]
+    /// Tests that every move is scored and that the
+    /// scores agree with the best move's score
+    fn test_analyze() {
+        let mut state = State::new();
+        for col in 0 .. 3 {
+            state[(5, col)] = Cell::Player { player: Player::Red };
+        }
+        state[(5, 6)] = Cell::Player { player: Player::Yellow };
+
+        let analysis = state.analyze(2, &Windows, &TranspositionTable::new(), 2, &[]);
+        let moves = analysis.iter().map(|&(mv, _)| mv).collect::<Vec<_>>();
+
+        assert_eq!(
+            moves, state.get_valid(),
+            "Expected every legal move to be scored, got {:?}",
+            moves
+        );
+
+        let best = analysis.iter().max_by_key(|&&(_, score)| score).unwrap();
+
+        assert_eq!(
+            *best, (Move::Drop(3), Score::win_in(1)),
+            "Expected the winning move to score best, got {:?}",
+            best
+        );
+    }
+
+    #[test]
+    /// Tests that a search stops once its time is up
+    fn test_search_time() {
+        let state = State::new();
+        let limits = SearchLimits::depth(20).with_time(Duration::from_millis(50));
+
+        let start = Instant::now();
+        let result = state.best(limits, state.player(), &Windows, &TranspositionTable::new(), 1);
+
+        assert!(
+            start.elapsed() < Duration::from_secs(2) && result.depth < 20,
+            "Expected the search to stop early, got {} after {:?}",
+            result, start.elapsed()
+        );
+
+        assert!(
+            result.best_move.is_some(),
+            "Expected a move in any case, but there was none",
+        );
+    }
+
+    #[test]
+    /// Tests that the score is from the perspective of the
+    /// given player, even if an opponent is to move
+    fn test_search_perspective() {
+        let mut state = State::new();
+        for col in [3, 3, 2] {
+            state.play(Move::Drop(col));
+        }
+
+        let tt = TranspositionTable::new();
+        let mover = state.best(SearchLimits::depth(4), state.player(), &Windows, &tt, 1);
+        let other = state.best(SearchLimits::depth(4), state.player().other(), &Windows, &tt, 1);
+
+        assert_eq!(
+            (other.score, other.best_move), (-mover.score, mover.best_move),
+            "Expected the same move with the negated score, got {} instead of {}",
+            other.score, mover.score
+        );
+
+        // green completes a line in the bottom row right away
+        let mut state = State::with_size(7, 9).with_players(3);
+        for col in 1 .. 4 {
+            state[(6, col)] = Cell::Player { player: Player::Green };
+        }
+        state.turn = Player::Green;
+
+        let result = state.best(SearchLimits::depth(1), Player::Red, &Windows, &tt, 1);
+
+        assert!(
+            result.score.is_loss() && matches!(result.best_move, Some(Move::Drop(0 | 4))),
+            "Expected green's win to be a loss for red, got {} ({:?})",
+            result.score, result.best_move
+        );
+    }
+}
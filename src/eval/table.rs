@@ -0,0 +1,392 @@
+use std::{fs, sync::atomic::{AtomicU64, Ordering}};
+
+use crate::{board::State, rules::Variant};
+
+use super::score::Score;
+
+pub type TranspositionKey = u64;
+
+/// The default number of entries of a transposition table (16 MiB)
+pub const TABLE_ENTRIES: usize = 1 << 20;
+
+/// The first bytes of every cache file, identifying its format
+pub const CACHE_MAGIC: &[u8; 4] = b"C4TT";
+
+/// The version of the cache file format. Keys (see `State::canonical_hash`)
+/// and entries (see `pack`) are stored as they are, so the version has to
+/// change whenever either of them does, making older caches unusable.
+pub const CACHE_VERSION: u8 = 4;
+
+/// The minimum depth of the (exact) entries kept in a cache file
+pub const CACHE_DEPTH: usize = 6;
+
+/// The size of the header of a cache file (see `to_bytes`)
+const CACHE_HEADER: usize = 27;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TranspositionEntry {
+    pub eval: Score,
+    pub depth: usize,
+    pub flag: TranspositionFlag
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TranspositionFlag {
+    EXACT,
+    LOWERBOUND,
+    UPPERBOUND,
+}
+
+/// A single slot of the table. The key is stored xor-ed with
+/// the data, so that a slot written by two threads at once (and
+/// thus holding the key of one and the data of the other) is
+/// detected as a mismatch rather than returning wrong data.
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+/// A fixed-size, lock-free transposition table that can be shared
+/// by all threads of a (parallel) search. Entries are replaced
+/// whenever another position maps to the same slot.
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+}
+
+impl TranspositionTable {
+    /// Returns an empty table with `TABLE_ENTRIES` entries
+    pub fn new() -> TranspositionTable {
+        TranspositionTable::with_entries(TABLE_ENTRIES)
+    }
+
+    /// Returns an empty table with (at least) the given number
+    /// of entries, rounded up to the next power of two
+    pub fn with_entries(entries: usize) -> TranspositionTable {
+        let slots = (0 .. entries.max(1).next_power_of_two()).map(|_| Slot::default()).collect();
+        TranspositionTable { slots }
+    }
+
+    /// Returns the number of entries of the table
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns whether the table has no entries at all
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Returns the entry stored for the given key, if any
+    pub fn get(&self, key: &TranspositionKey) -> Option<TranspositionEntry> {
+        let slot = self.slot(*key);
+        let data = slot.data.load(Ordering::Relaxed);
+
+        if slot.key.load(Ordering::Relaxed) ^ data != *key {
+            return None;
+        }
+
+        unpack(data)
+    }
+
+    /// Stores the given entry for the given key, replacing
+    /// whatever was stored in its slot before
+    pub fn insert(&self, key: TranspositionKey, entry: TranspositionEntry) {
+        let slot = self.slot(key);
+        let data = pack(entry);
+
+        slot.key.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+
+    /// Removes all entries from the table
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns all entries stored in the table with their keys
+    pub fn entries(&self) -> impl Iterator<Item = (TranspositionKey, TranspositionEntry)> + '_ {
+        self.slots.iter().filter_map(|slot| {
+            let data = slot.data.load(Ordering::Relaxed);
+            unpack(data).map(|entry| (slot.key.load(Ordering::Relaxed) ^ data, entry))
+        })
+    }
+
+    /// Returns the entries worth keeping across runs in the cache file
+    /// format: exact entries of decided games or searched to at least
+    /// the given depth, of positions of the given state's board and rules,
+    /// scored by the evaluator of the given fingerprint (see
+    /// `Evaluator::fingerprint`). The format is a header (`CACHE_MAGIC`,
+    /// `CACHE_VERSION`, the board and rules (see `cache_board`), followed
+    /// by the fingerprint and the number of entries as u64), then the key
+    /// and packed entry of every entry (u64 each), all numbers little endian.
+    pub fn to_bytes(&self, min_depth: usize, board: &State, fingerprint: u64) -> Vec<u8> {
+        let entries = self
+            .entries()
+            .filter(|(_, entry)| entry.flag == TranspositionFlag::EXACT && (entry.eval.is_decided() || entry.depth >= min_depth))
+            .collect::<Vec<_>>();
+
+        let mut bytes = CACHE_MAGIC.to_vec();
+        bytes.push(CACHE_VERSION);
+        bytes.extend(cache_board(board));
+        bytes.extend(fingerprint.to_le_bytes());
+        bytes.extend((entries.len() as u64).to_le_bytes());
+
+        for (key, entry) in entries {
+            bytes.extend(key.to_le_bytes());
+            bytes.extend(pack(entry).to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// Stores the entries of the given cache file format (see
+    /// `to_bytes`) and returns their number. A cache of another version,
+    /// of another board or rules than the given state's ones or of
+    /// another evaluator than the one of the given fingerprint is
+    /// rejected without storing any of its entries.
+    pub fn restore(&self, bytes: &[u8], board: &State, fingerprint: u64) -> Result<usize, String> {
+        if bytes.len() < CACHE_HEADER || &bytes[.. 4] != CACHE_MAGIC {
+            return Err("not a cache file".to_string());
+        }
+
+        if bytes[4] != CACHE_VERSION {
+            return Err(format!("cache of version {}, expected version {}", bytes[4], CACHE_VERSION));
+        }
+
+        if bytes[5 .. 11] != cache_board(board) {
+            return Err("cache of another board or variant".to_string());
+        }
+
+        if u64::from_le_bytes(bytes[11 .. 19].try_into().expect("8 bytes")) != fingerprint {
+            return Err("cache of another evaluation (see --eval, --params and --network)".to_string());
+        }
+
+        let len = u64::from_le_bytes(bytes[19 .. CACHE_HEADER].try_into().expect("8 bytes")) as usize;
+        let body = &bytes[CACHE_HEADER ..];
+        if len.checked_mul(16) != Some(body.len()) {
+            return Err("truncated cache file".to_string());
+        }
+
+        for record in body.chunks(16) {
+            let key = u64::from_le_bytes(record[.. 8].try_into().expect("8 bytes"));
+            let data = u64::from_le_bytes(record[8 ..].try_into().expect("8 bytes"));
+
+            if let Some(entry) = unpack(data) {
+                self.insert(key, entry);
+            }
+        }
+
+        Ok(len)
+    }
+
+    /// Reads the entries of the given cache file into the table
+    /// (see `restore`) and returns their number
+    pub fn load(&self, path: &str, board: &State, fingerprint: u64) -> Result<usize, String> {
+        let bytes = fs::read(path).map_err(|err| format!("cannot read '{}': {}", path, err))?;
+        self.restore(&bytes, board, fingerprint)
+    }
+
+    /// Writes the entries worth keeping (see `to_bytes`) to
+    /// the given cache file and returns their number
+    pub fn save(&self, path: &str, min_depth: usize, board: &State, fingerprint: u64) -> Result<usize, String> {
+        let bytes = self.to_bytes(min_depth, board, fingerprint);
+        fs::write(path, &bytes).map_err(|err| format!("cannot write '{}': {}", path, err))?;
+
+        Ok((bytes.len() - CACHE_HEADER) / 16)
+    }
+
+    fn slot(&self, key: TranspositionKey) -> &Slot {
+        &self.slots[key as usize & (self.slots.len() - 1)]
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        TranspositionTable::new()
+    }
+}
+
+/// Returns the board and rules of the given state as stored in the
+/// header of a cache file: rows, cols, win length, variant, cylinder
+/// and number of players, a byte each
+fn cache_board(state: &State) -> [u8; 6] {
+    let variant = Variant::ALL.iter().position(|&variant| variant == state.variant).expect("a known variant");
+    [state.rows(), state.cols(), state.win_length, variant, state.cylinder as usize, state.players].map(|value| value as u8)
+}
+
+/// Marks a slot as used, as an empty slot is all zeros
+const USED: u64 = 1 << 63;
+
+/// Packs an entry into 64 bits:
+///     bits 0 - 31: eval, bits 32 - 47: depth, bits 48 - 49: flag
+fn pack(entry: TranspositionEntry) -> u64 {
+    let flag = match entry.flag {
+        TranspositionFlag::EXACT => 0,
+        TranspositionFlag::LOWERBOUND => 1,
+        TranspositionFlag::UPPERBOUND => 2,
+    };
+
+    USED | entry.eval.0 as u32 as u64 | (entry.depth.min(u16::MAX as usize) as u64) << 32 | flag << 48
+}
+
+/// Unpacks an entry packed by `pack`
+fn unpack(data: u64) -> Option<TranspositionEntry> {
+    if data & USED == 0 {
+        return None;
+    }
+
+    let flag = match (data >> 48) & 0b11 {
+        0 => TranspositionFlag::EXACT,
+        1 => TranspositionFlag::LOWERBOUND,
+        _ => TranspositionFlag::UPPERBOUND,
+    };
+
+    Some(TranspositionEntry {
+        eval: Score(data as u32 as i32),
+        depth: ((data >> 32) & 0xFFFF) as usize,
+        flag,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests that entries are stored and looked up by their key
+    fn test_get_insert() {
+        let tt = TranspositionTable::with_entries(16);
+        let entry = TranspositionEntry { eval: Score::loss_in(3), depth: 7, flag: TranspositionFlag::UPPERBOUND };
+
+        assert_eq!(
+            tt.get(&42), None,
+            "Expected an empty table, got {:?}",
+            tt.get(&42)
+        );
+
+        tt.insert(42, entry);
+
+        assert_eq!(
+            tt.get(&42), Some(entry),
+            "Expected {:?}, got {:?}",
+            entry, tt.get(&42)
+        );
+
+        // same slot, different key
+        assert_eq!(
+            tt.get(&(42 + 16)), None,
+            "Expected no entry for a different key, got {:?}",
+            tt.get(&(42 + 16))
+        );
+
+        tt.clear();
+
+        assert_eq!(
+            tt.get(&42), None,
+            "Expected a cleared table, got {:?}",
+            tt.get(&42)
+        );
+    }
+
+    #[test]
+    /// Tests that the table can be used by several threads at once
+    fn test_threads() {
+        let tt = TranspositionTable::with_entries(1024);
+
+        std::thread::scope(|scope| {
+            for thread in 0 .. 4 {
+                let tt = &tt;
+                scope.spawn(move || {
+                    for key in (thread .. 4096).step_by(4) {
+                        tt.insert(key, TranspositionEntry { eval: Score(key as i32), depth: 1, flag: TranspositionFlag::EXACT });
+                    }
+                });
+            }
+        });
+
+        for key in 0 .. 4096 {
+            if let Some(entry) = tt.get(&key) {
+                assert_eq!(
+                    entry.eval, Score(key as i32),
+                    "Expected the entry of key {} to match its key, got {:?}",
+                    key, entry
+                );
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that exact entries of decided games or deep searches
+    /// are kept in a cache, and that other versions and caches
+    /// of other evaluators are rejected
+    fn test_cache() {
+        let tt = TranspositionTable::with_entries(64);
+        let deep = TranspositionEntry { eval: Score(35), depth: 8, flag: TranspositionFlag::EXACT };
+        let decided = TranspositionEntry { eval: Score::win_in(3), depth: 2, flag: TranspositionFlag::EXACT };
+
+        tt.insert(1, deep);
+        tt.insert(2, decided);
+        tt.insert(3, TranspositionEntry { eval: Score(10), depth: 2, flag: TranspositionFlag::EXACT });
+        tt.insert(4, TranspositionEntry { eval: Score(10), depth: 9, flag: TranspositionFlag::LOWERBOUND });
+
+        let board = State::new();
+        let bytes = tt.to_bytes(CACHE_DEPTH, &board, 7);
+        let cached = TranspositionTable::with_entries(1024);
+
+        assert_eq!(
+            cached.restore(&bytes, &board, 7), Ok(2),
+            "Expected two entries to be cached, got {:?}",
+            cached.entries().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            (cached.get(&1), cached.get(&2), cached.get(&3), cached.get(&4)), (Some(deep), Some(decided), None, None),
+            "Expected the deep and decided entries only, got {:?}",
+            cached.entries().collect::<Vec<_>>()
+        );
+
+        let mut outdated = bytes.clone();
+        outdated[4] = CACHE_VERSION + 1;
+
+        assert!(
+            TranspositionTable::new().restore(&outdated, &board, 7).is_err() && TranspositionTable::new().restore(&bytes[.. bytes.len() - 1], &board, 7).is_err(),
+            "Expected outdated and truncated caches to be rejected, but they weren't",
+        );
+
+        assert!(
+            TranspositionTable::new().restore(&bytes, &board, 8).is_err(),
+            "Expected a cache of another evaluator to be rejected, but it wasn't",
+        );
+
+        let mut huge = bytes.clone();
+        huge[19 .. 27].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        assert!(
+            TranspositionTable::new().restore(&huge, &board, 7).is_err(),
+            "Expected a cache of an impossible length to be rejected, but it wasn't",
+        );
+    }
+
+    #[test]
+    /// Tests that a cache of one board is rejected on
+    /// a board of another size or with other rules
+    fn test_cache_board() {
+        let tt = TranspositionTable::with_entries(64);
+        tt.insert(1, TranspositionEntry { eval: Score::win_in(3), depth: 2, flag: TranspositionFlag::EXACT });
+        let bytes = tt.to_bytes(CACHE_DEPTH, &State::new(), 7);
+
+        for other in [State::with_size(6, 8), State::new().with_win_length(3), State::new().with_cylinder(true)] {
+            let restored = TranspositionTable::new().restore(&bytes, &other, 7);
+
+            assert!(
+                restored.is_err(),
+                "Expected the cache of a 6x7 board to be rejected on\n{}got {:?}",
+                other, restored
+            );
+        }
+    }
+}
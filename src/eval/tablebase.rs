@@ -0,0 +1,391 @@
+use std::{collections::{HashMap, HashSet}, fs, sync::OnceLock};
+
+use crate::{board::State, rules::Variant};
+
+use super::score::Score;
+
+/// The first bytes of every tablebase file, identifying its format
+pub const MAGIC: &[u8; 4] = b"C4TB";
+
+/// The version of the file format
+pub const VERSION: u8 = 3;
+
+/// The default maximum number of empty cells of a solved position
+pub const MAX_EMPTY: usize = 10;
+
+/// The default maximum number of positions visited (or solved) by
+/// the generation, bounding its memory (about 40 bytes per position)
+pub const MAX_POSITIONS: usize = 20_000_000;
+
+/// The number of positions between two progress reports of the generation
+const PROGRESS_INTERVAL: usize = 1_000_000;
+
+/// The tablebase installed at runtime, if any (see `Tablebase::install`)
+static INSTALLED: OnceLock<Tablebase> = OnceLock::new();
+
+/// The exact results of all positions (of one board and rule set)
+/// with at most `max_empty` empty cells that are reachable from
+/// some root position. Positions are looked up by their canonical
+/// key (see `State::canonical_hash`) in a sorted index, results are
+/// stored by their distance to the end of the game in plies:
+///     win in n plies => n, loss in n plies => -n, draw => 0
+/// Finished games are not stored, so a loss is never 0 plies away.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Tablebase {
+    pub rows: usize,
+    pub cols: usize,
+    pub win_length: usize,
+    pub variant: Variant,
+    pub cylinder: bool,
+
+    /// the maximum number of empty cells of a stored position
+    pub max_empty: usize,
+
+    /// the sorted keys of the positions and their results
+    keys: Vec<u64>,
+    results: Vec<i16>,
+}
+
+impl Tablebase {
+    /// Solves every position with at most the given number of empty
+    /// cells that is reachable from the given root (which may have any
+    /// number of empty cells). Only two-player games whose positions
+    /// can't repeat are supported. As the number of positions grows
+    /// quickly with the empty cells of the root, the generation fails
+    /// once it visited more than the given number of positions. The
+    /// given callback is called with the number of positions visited
+    /// every `PROGRESS_INTERVAL` positions.
+    pub fn generate(root: &State, max_empty: usize, max_positions: usize, mut progress: impl FnMut(usize)) -> Result<Tablebase, String> {
+        if root.players != 2 || root.rules().can_repeat() {
+            return Err(format!("tablebases are generated for two-player games of variants without repetitions, not '{}'", root.variant));
+        }
+
+        let mut generation = Generation { results: HashMap::new(), visited: HashSet::new(), max_positions, progress: &mut progress };
+        visit(root, max_empty, &mut generation)?;
+
+        let mut entries = generation.results.into_iter().collect::<Vec<_>>();
+        entries.sort_unstable();
+
+        Ok(Tablebase {
+            rows: root.rows(),
+            cols: root.cols(),
+            win_length: root.win_length,
+            variant: root.variant,
+            cylinder: root.cylinder,
+            max_empty,
+            keys: entries.iter().map(|&(key, _)| key).collect(),
+            results: entries.iter().map(|&(_, result)| result).collect(),
+        })
+    }
+
+    /// Returns the number of positions stored
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns whether no positions are stored at all
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the tablebase installed at runtime, if any
+    pub fn current() -> Option<&'static Tablebase> {
+        INSTALLED.get()
+    }
+
+    /// Installs the tablebase probed by the search from now on.
+    /// A tablebase can only be installed once (e.g. at startup).
+    pub fn install(self) -> Result<(), String> {
+        INSTALLED.set(self).map_err(|_| "a tablebase is already installed".to_string())
+    }
+
+    /// Returns whether the tablebase holds positions
+    /// of the given state's board and rules
+    pub fn fits(&self, state: &State) -> bool {
+        (self.rows, self.cols, self.win_length, self.variant, self.cylinder) == (state.rows(), state.cols(), state.win_length, state.variant, state.cylinder)
+            && state.players == 2
+    }
+
+    /// Returns the exact score of the given state (from the perspective
+    /// of the player to move), whose canonical key is given, if it's
+    /// stored in the tablebase
+    pub fn probe(&self, state: &State, key: u64) -> Option<Score> {
+        if !self.fits(state) || state.empty_cells() > self.max_empty {
+            return None;
+        }
+
+        let index = self.keys.binary_search(&key).ok()?;
+        Some(decode(self.results[index]))
+    }
+
+    /// Reads the tablebase from the given file
+    pub fn load(path: &str) -> Result<Tablebase, String> {
+        let bytes = fs::read(path).map_err(|err| format!("cannot read '{}': {}", path, err))?;
+        Tablebase::from_bytes(&bytes)
+    }
+
+    /// Writes the tablebase to the given file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|err| format!("cannot write '{}': {}", path, err))
+    }
+
+    /// Returns the tablebase in its file format: a header (`MAGIC`,
+    /// `VERSION`, rows, cols, win length, variant, cylinder and the
+    /// maximum number of empty cells, a byte each, followed by the
+    /// number of positions as u64), then the sorted keys (u64 each)
+    /// and the results (i16 each), all numbers little endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let variant = Variant::ALL.iter().position(|&variant| variant == self.variant).expect("a known variant");
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([VERSION, self.rows as u8, self.cols as u8, self.win_length as u8, variant as u8, self.cylinder as u8, self.max_empty as u8]);
+        bytes.extend((self.len() as u64).to_le_bytes());
+
+        for key in &self.keys {
+            bytes.extend(key.to_le_bytes());
+        }
+        for result in &self.results {
+            bytes.extend(result.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// Reads a tablebase in its file format (see `to_bytes`)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Tablebase, String> {
+        const HEADER: usize = 19;
+
+        if bytes.len() < HEADER || &bytes[.. 4] != MAGIC || bytes[4] != VERSION {
+            return Err("not a tablebase (or of an unknown version)".to_string());
+        }
+
+        let header = &bytes[5 .. 11];
+        let variant = *Variant::ALL.get(header[3] as usize).ok_or("unknown variant of the tablebase")?;
+        let len = u64::from_le_bytes(bytes[11 .. HEADER].try_into().expect("8 bytes")) as usize;
+
+        let body = &bytes[HEADER ..];
+        if len.checked_mul(10) != Some(body.len()) {
+            return Err("truncated tablebase".to_string());
+        }
+
+        let (keys, results) = body.split_at(len * 8);
+        let keys = keys.chunks(8).map(|key| u64::from_le_bytes(key.try_into().expect("8 bytes"))).collect::<Vec<_>>();
+        let results = results.chunks(2).map(|result| i16::from_le_bytes(result.try_into().expect("2 bytes"))).collect();
+
+        if !keys.is_sorted() {
+            return Err("the positions of the tablebase are not sorted".to_string());
+        }
+
+        Ok(Tablebase {
+            rows: header[0] as usize,
+            cols: header[1] as usize,
+            win_length: header[2] as usize,
+            variant,
+            cylinder: header[4] != 0,
+            max_empty: header[5] as usize,
+            keys,
+            results,
+        })
+    }
+}
+
+impl State {
+    /// Returns the number of empty cells on the board
+    pub fn empty_cells(&self) -> usize {
+        self.data.iter().flatten().filter(|cell| cell.is_empty()).count()
+    }
+
+    /// Returns the exact score of the state (from the perspective of
+    /// the player to move) by searching all of its moves to the end of
+    /// the game. The results of all non-terminal positions searched
+    /// are stored by their canonical key (see `Tablebase`).
+    fn solve(&self, generation: &mut Generation) -> Result<Score, String> {
+        if self.is_terminal() {
+            return Ok(match self.get_winner() {
+                Some(winner) if winner == self.player() => Score::win_in(0),
+                Some(_) => Score::loss_in(0),
+                None => Score::DRAW,
+            });
+        }
+
+        let key = self.canonical_hash();
+        if let Some(&result) = generation.results.get(&key) {
+            return Ok(decode(result));
+        }
+
+        let moves = self.get_valid();
+        let score = match moves.iter().any(|&mv| self.rules().wins_with(self, mv)) {
+            true => Score::win_in(1),
+            false => {
+                let mut best = None;
+                for mv in moves {
+                    best = best.max(Some(self.played(mv).solve(generation)?.parent()));
+                }
+                best.unwrap_or(Score::DRAW)
+            }
+        };
+
+        generation.results.insert(key, encode(score));
+        generation.count()?;
+        Ok(score)
+    }
+}
+
+/// The positions solved and visited by a tablebase generation
+struct Generation<'a> {
+    results: HashMap<u64, i16>,
+    visited: HashSet<u64>,
+    max_positions: usize,
+    progress: &'a mut dyn FnMut(usize),
+}
+
+impl Generation<'_> {
+    /// Counts a newly solved or visited position, reporting the
+    /// progress and failing once there are too many positions
+    fn count(&mut self) -> Result<(), String> {
+        let positions = self.results.len() + self.visited.len();
+
+        if positions > self.max_positions {
+            return Err(format!("more than {} positions to solve, try a later --root or fewer --empty cells", self.max_positions));
+        }
+        if positions.is_multiple_of(PROGRESS_INTERVAL) {
+            (self.progress)(positions);
+        }
+
+        Ok(())
+    }
+}
+
+/// Visits all positions reachable from the given one, solving
+/// those with at most the given number of empty cells
+fn visit(state: &State, max_empty: usize, generation: &mut Generation) -> Result<(), String> {
+    if state.is_terminal() {
+        return Ok(());
+    }
+
+    if state.empty_cells() <= max_empty {
+        state.solve(generation)?;
+        return Ok(());
+    }
+
+    if !generation.visited.insert(state.canonical_hash()) {
+        return Ok(());
+    }
+    generation.count()?;
+
+    for mv in state.get_valid() {
+        visit(&state.played(mv), max_empty, generation)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the stored result of a decided (or drawn) score
+fn encode(score: Score) -> i16 {
+    match score.plies_to_end() {
+        Some(plies) if score.is_win() => plies as i16,
+        Some(plies) => -(plies as i16),
+        None => 0,
+    }
+}
+
+/// Returns the score of a stored result (see `encode`)
+fn decode(result: i16) -> Score {
+    match result {
+        0 => Score::DRAW,
+        plies if plies > 0 => Score::win_in(plies as u32),
+        plies => Score::loss_in(plies.unsigned_abs() as u32),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{board::Move, eval::{evaluator::Zero, search::SearchLimits, table::TranspositionTable}};
+
+    /// A small board, solved completely
+    fn small() -> State {
+        State::with_size(4, 4).with_win_length(3)
+    }
+
+    #[test]
+    /// Tests that the tablebase agrees with the solver
+    fn test_generate() {
+        let root = small().played(Move::Drop(1)).played(Move::Drop(2));
+        let tablebase = Tablebase::generate(&root, 8, MAX_POSITIONS, |_| ()).unwrap();
+
+        assert!(
+            !tablebase.is_empty() && tablebase.probe(&root, root.canonical_hash()).is_none(),
+            "Expected positions with at most 8 empty cells only, got {} positions",
+            tablebase.len()
+        );
+
+        let mut state = root.clone();
+        for col in [0, 3, 3, 0, 1, 2] {
+            state.play(Move::Drop(col));
+        }
+
+        let probed = tablebase.probe(&state, state.canonical_hash());
+        let solved = state.best(SearchLimits::depth(state.empty_cells()), state.player(), &Zero, &TranspositionTable::new(), 1).score;
+
+        assert_eq!(
+            probed, Some(solved),
+            "Expected the solver's score, got {:?}",
+            probed
+        );
+
+        assert!(
+            Tablebase::generate(&State::new().with_variant(Variant::PopOut), 4, MAX_POSITIONS, |_| ()).is_err(),
+            "Expected variants with repetitions to be rejected, but they weren't",
+        );
+    }
+
+    #[test]
+    /// Tests that a generation visiting too many positions fails
+    fn test_generate_limit() {
+        let mut reports = 0;
+        let generated = Tablebase::generate(&State::new(), MAX_EMPTY, 1000, |_| reports += 1);
+
+        assert!(
+            generated.is_err() && reports == 0,
+            "Expected the empty board to exceed 1000 positions, got {:?}",
+            generated.map(|tablebase| tablebase.len())
+        );
+    }
+
+    #[test]
+    /// Tests that a tablebase is written to and read
+    /// from its file format without changing it
+    fn test_file() {
+        let tablebase = Tablebase::generate(&small().played(Move::Drop(0)), 6, MAX_POSITIONS, |_| ()).unwrap();
+        let read = Tablebase::from_bytes(&tablebase.to_bytes());
+
+        assert_eq!(
+            read, Ok(tablebase.clone()),
+            "Expected the tablebase to be read back, got {:?}",
+            read.as_ref().map(Tablebase::len)
+        );
+
+        let bytes = tablebase.to_bytes();
+        let mut huge = bytes.clone();
+        huge[11 .. 19].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        assert!(
+            Tablebase::from_bytes(&bytes[.. bytes.len() - 1]).is_err() && Tablebase::from_bytes(b"bogus").is_err() && Tablebase::from_bytes(&huge).is_err(),
+            "Expected invalid files to be rejected, but they weren't",
+        );
+    }
+
+    #[test]
+    /// Tests that results are stored by their distance
+    fn test_encode() {
+        for score in [Score::win_in(1), Score::win_in(7), Score::loss_in(2), Score::loss_in(12), Score::DRAW] {
+            assert_eq!(
+                decode(encode(score)), score,
+                "Expected {} to be stored exactly, got {}",
+                score, decode(encode(score))
+            );
+        }
+    }
+}
+
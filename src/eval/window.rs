@@ -0,0 +1,241 @@
+use crate::{board::{Cell, Player, State}, detect::{threats::threats, DIRECTIONS}};
+
+use super::score::MAX_HEURISTIC;
+
+/// The score of a window missing a single piece (e.g. an open three)
+const MISSING_ONE: f32 = 5.0;
+
+/// The score of a window missing two pieces (e.g. an open two)
+const MISSING_TWO: f32 = 2.0;
+
+/// The score of a threat (an empty cell completing a line)
+const THREAT: f32 = 4.0;
+
+/// The additional score of a threat on a row its player can
+/// usually force (odd rows for the first player, even rows for
+/// the second one, see `Threat::is_odd`)
+const GOOD_PARITY: f32 = 6.0;
+
+/// The score of a double threat (two immediate threats, or two
+/// threats on top of each other), which usually can't be stopped
+const DOUBLE_THREAT: f32 = 40.0;
+
+/// The score of a collected piece (Pop 10)
+const CAPTURED: f32 = 10.0;
+
+/// Calls the given function with the cells of every window, i.e.
+/// every line of `win_length` cells on the board (including the
+/// ones wrapping around a cylinder)
+fn for_each_window(state: &State, mut f: impl FnMut(&[Cell])) {
+    let (rows, cols) = (state.rows() as i32, state.cols() as i32);
+    let len = state.win_length as i32;
+    let mut window = Vec::with_capacity(state.win_length);
+
+    for (dr, dc) in DIRECTIONS.iter() {
+        // a line can't wrap onto itself
+        if state.cylinder && *dc != 0 && len > cols {
+            continue;
+        }
+
+        for row in 0 .. rows {
+            for col in 0 .. cols {
+                let (r1, c1) = (row + dr * (len - 1), col + dc * (len - 1));
+                if r1 < 0 || r1 >= rows || (!state.cylinder && (c1 < 0 || c1 >= cols)) {
+                    continue;
+                }
+
+                window.clear();
+                for i in 0 .. len {
+                    let c = (col + dc * i).rem_euclid(cols);
+                    window.push(state[((row + dr * i) as usize, c as usize)]);
+                }
+
+                f(&window);
+            }
+        }
+    }
+}
+
+impl State {
+    /// Returns the window evaluation of the current state from
+    /// the perspective of the current player (see `evaluate_windows_for`)
+    pub fn evaluate_windows(&self) -> f32 {
+        self.evaluate_windows_for(self.player())
+    }
+
+    /// Returns the window evaluation of the current state from
+    /// the perspective of the given player. Every window is scored
+    /// for the only player with pieces in it, as are the player's
+    /// threats. The player's score counts in their favor, the one
+    /// of their strongest opponent against.
+    ///     player wins => WIN_SCORE
+    ///     any opponent wins => -WIN_SCORE
+    pub fn evaluate_windows_for(&self, player: Player) -> f32 {
+        if let Some(outcome) = self.outcome_for(player) {
+            return outcome;
+        }
+
+        let mut scores = [0.0; Player::ALL.len()];
+
+        for_each_window(self, |window| {
+            let mut owner = None;
+            let mut count = 0;
+
+            for cell in window {
+                match (cell, owner) {
+                    (Cell::Empty, _) => (),
+                    (Cell::Player { player }, None) => {
+                        owner = Some(*player);
+                        count += 1;
+                    }
+                    (Cell::Player { player }, Some(owner)) if *player == owner => count += 1,
+
+                    // a window with pieces of several players is blocked
+                    _ => return,
+                }
+            }
+
+            if let Some(owner) = owner {
+                scores[owner.index()] += match self.win_length - count {
+                    1 => MISSING_ONE,
+                    2 => MISSING_TWO,
+                    _ => 0.0,
+                };
+            }
+        });
+
+        for &p in &Player::ALL[.. self.players] {
+            scores[p.index()] += self.evaluate_threats(p) + self.captured[p.index()] as f32 * CAPTURED;
+        }
+
+        self.relative(&scores, player)
+    }
+
+    /// Returns the threat evaluation of the current state from the
+    /// perspective of the given player, i.e. the window evaluation
+    /// without the windows (see `evaluate_windows_for`)
+    pub fn evaluate_threats_for(&self, player: Player) -> f32 {
+        if let Some(outcome) = self.outcome_for(player) {
+            return outcome;
+        }
+
+        let mut scores = [0.0; Player::ALL.len()];
+        for &p in &Player::ALL[.. self.players] {
+            scores[p.index()] = self.evaluate_threats(p) + self.captured[p.index()] as f32 * CAPTURED;
+        }
+
+        self.relative(&scores, player)
+    }
+
+    /// Returns the score of the given player relative to the one
+    /// of their strongest opponent, bounded by `MAX_HEURISTIC`
+    fn relative(&self, scores: &[f32], player: Player) -> f32 {
+        let opponent = Player::ALL[.. self.players]
+            .iter()
+            .filter(|&&p| p != player)
+            .map(|&p| scores[p.index()])
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        (scores[player.index()] - opponent).clamp(-MAX_HEURISTIC, MAX_HEURISTIC)
+    }
+
+    /// Scores the threats of the given player, rewarding threats on
+    /// rows of the player's parity (two-player games only) and
+    /// double threats
+    fn evaluate_threats(&self, player: Player) -> f32 {
+        let threats = threats(self, player);
+        let mut score = threats.len() as f32 * THREAT;
+
+        if self.players == 2 {
+            let odd = player == Player::ALL[0];
+            score += threats.iter().filter(|t| t.is_odd() == odd).count() as f32 * GOOD_PARITY;
+        }
+
+        let immediate = threats.iter().filter(|t| t.is_immediate()).count();
+        let stacked = threats.windows(2).any(|pair| {
+            pair[0].col == pair[1].col && pair[0].height + 1 == pair[1].height
+        });
+
+        if immediate >= 2 || stacked {
+            score += DOUBLE_THREAT;
+        }
+
+        score
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::eval::score::WIN_SCORE;
+
+    #[test]
+    /// Tests that the evaluation is relative to the player to
+    /// move, i.e. the same position is scored with opposite
+    /// signs for both players
+    fn test_evaluate_windows_perspective() {
+        let mut state = State::new();
+        state.drop(3, Player::Red);
+        state.drop(0, Player::Yellow);
+        state.drop(2, Player::Red);
+
+        let eval = state.evaluate_windows();
+
+        assert!(
+            eval < 0.0,
+            "Expected yellow (to move) to be worse off, got {}",
+            eval
+        );
+
+        assert_eq!(
+            state.evaluate_windows_for(Player::Red), -eval,
+            "Expected red's evaluation to be the negated one of yellow, got {}",
+            state.evaluate_windows_for(Player::Red)
+        );
+    }
+
+    #[test]
+    /// Tests that an open three is worth more than an open two,
+    /// and that a double threat is worth more than a single one
+    fn test_evaluate_windows_threes() {
+        let mut two = State::new();
+        let mut three = State::new();
+        for col in 2 .. 4 {
+            two[(5, col)] = Cell::Player { player: Player::Red };
+            three[(5, col)] = Cell::Player { player: Player::Red };
+        }
+
+        // R _ R R has a single threat, _ _ R R R _ a double one
+        let mut double = three.clone();
+        three[(5, 0)] = Cell::Player { player: Player::Red };
+        double[(5, 4)] = Cell::Player { player: Player::Red };
+
+        let (two, three, double) = (
+            two.evaluate_windows_for(Player::Red),
+            three.evaluate_windows_for(Player::Red),
+            double.evaluate_windows_for(Player::Red),
+        );
+
+        assert!(
+            two < three && three + DOUBLE_THREAT <= double,
+            "Expected two < three < double threat, got {} / {} / {}",
+            two, three, double
+        );
+    }
+
+    #[test]
+    /// Tests that a won game is scored with the win score
+    fn test_evaluate_windows_win() {
+        let mut state = State::new();
+        for row in 2 .. 6 {
+            state[(row, 0)] = Cell::Player { player: Player::Yellow };
+        }
+
+        assert_eq!(
+            (state.evaluate_windows_for(Player::Yellow), state.evaluate_windows_for(Player::Red)),
+            (WIN_SCORE, -WIN_SCORE),
+            "Expected the win score for yellow, got {}",
+            state.evaluate_windows_for(Player::Yellow)
+        );
+    }
+}
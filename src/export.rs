@@ -0,0 +1,519 @@
+use std::{collections::HashSet, fmt, fs, str::FromStr};
+
+use crate::{
+    board::{Board, Cell, Move, Player, State},
+    config::Config,
+    eval::{engine::Engine, random::Rng, score::Score, search::SearchResult},
+    tournament::{apply_params, EngineSpec},
+};
+
+/// The default number of self-play games
+pub const GAMES: usize = 100;
+
+/// The default file the positions are written to
+pub const OUTPUT_FILE: &str = "positions.csv";
+
+/// The first bytes of every binary dataset, identifying its format
+pub const MAGIC: &[u8; 4] = b"C4DS";
+
+/// The version of the binary format
+pub const VERSION: u8 = 1;
+
+/// The formats the positions can be exported in
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Default)]
+pub enum Format {
+    /// comma separated values, with a header line
+    #[default]
+    Csv,
+
+    /// one JSON object per line
+    Jsonl,
+
+    /// fixed-size binary records (see `write_binary`)
+    Binary,
+}
+
+impl Format {
+    /// All formats, e.g. for listing them
+    pub const ALL: [Format; 3] = [Format::Csv, Format::Jsonl, Format::Binary];
+
+    /// Returns the name used to select the format on the command
+    /// line, which is also the extension of the format's files
+    pub fn key(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Jsonl => "jsonl",
+            Format::Binary => "bin",
+        }
+    }
+
+    /// Returns the format of the given file by its extension, if known
+    pub fn from_path(path: &str) -> Option<Format> {
+        let extension = path.rsplit_once('.')?.1;
+        Format::ALL.into_iter().find(|format| format.key() == extension)
+    }
+}
+
+impl fmt::Display for Format {
+    /// Display the name of the format (e.g. "JSONL")
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.key().to_uppercase())
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    /// Parse a format from its command line name (see `key`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Format::ALL
+            .into_iter()
+            .find(|format| format.key() == s.trim())
+            .ok_or_else(|| {
+                let keys = Format::ALL.map(|format| format.key()).join(", ");
+                format!("unknown format '{}', expected one of: {}", s, keys)
+            })
+    }
+}
+
+/// A position of a self-play game, labeled with
+/// the engine's search and the result of the game
+#[derive(Clone, PartialEq, Debug)]
+pub struct Record {
+    /// the canonical key of the position (see `State::canonical_hash`)
+    pub key: u64,
+
+    pub state: State,
+
+    /// the score of the engine's search, from the
+    /// perspective of the player to move (see `Score`)
+    pub score: Score,
+
+    /// the best move found by the search, if any
+    pub best: Option<Move>,
+
+    /// the result of the game for the player to
+    /// move: 1 for a win, 0 for a draw, -1 for a loss
+    pub result: i8,
+}
+
+impl Record {
+    /// Returns the position as a string of its rows, top to bottom,
+    /// separated by '/', with '.' for empty cells and 'r', 'y' and
+    /// 'g' for the pieces of the players, e.g. ".../..r/.yr"
+    pub fn position(&self) -> String {
+        self.state.data
+            .iter()
+            .map(|row| row.iter().map(|cell| cell_char(*cell)).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Returns the record as a line of comma separated values (see `CSV_HEADER`)
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{:016x},{},{},{},{},{}",
+            self.key,
+            self.position(),
+            player_char(self.state.player()),
+            self.score.0,
+            self.best.map_or(String::new(), |mv| mv.to_string()),
+            self.result,
+        )
+    }
+
+    /// Returns the record as a JSON object (on a single line)
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"key\":\"{:016x}\",\"position\":\"{}\",\"player\":\"{}\",\"score\":{},\"best\":{},\"result\":{}}}",
+            self.key,
+            self.position(),
+            player_char(self.state.player()),
+            self.score.0,
+            self.best.map_or("null".to_string(), |mv| format!("\"{}\"", mv)),
+            self.result,
+        )
+    }
+}
+
+/// The header line of the CSV format
+pub const CSV_HEADER: &str = "key,position,player,score,best,result";
+
+fn cell_char(cell: Cell) -> char {
+    cell.player().map_or('.', player_char)
+}
+
+fn player_char(player: Player) -> char {
+    match player {
+        Player::Red => 'r',
+        Player::Yellow => 'y',
+        Player::Green => 'g',
+    }
+}
+
+/// The configuration of the export
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportConfig {
+    /// the board (and rules) the games are played on
+    pub board: Config,
+
+    /// the engines playing the games, alternating colors
+    pub engines: [EngineSpec; 2],
+
+    pub games: usize,
+
+    /// the probability of playing a random move instead of
+    /// the engine's one, so that the games differ
+    pub noise: f32,
+
+    pub output: String,
+    pub format: Format,
+
+    /// the seed of the random moves
+    pub seed: Option<u64>,
+}
+
+impl ExportConfig {
+    /// Parses the configuration from the given command line
+    /// arguments (excluding the program name and the subcommand)
+    ///
+    /// Supported arguments (in addition to the board arguments of `Config`):
+    ///     --engine <spec>         the first engine (default: negamax:medium,
+    ///                             see `EngineSpec`)
+    ///     --opponent <spec>       the second engine (default: the first one)
+    ///     --games <n>             number of self-play games (default: 100)
+    ///     --noise <p>             probability of a random move (default: 0.1)
+    ///     --output <file>         file to write (default: positions.csv)
+    ///     --format <name>         csv, jsonl or bin (default: by the extension)
+    ///     --seed <n>              seed of the random moves
+    pub fn from_args<I>(args: I) -> Result<ExportConfig, String>
+    where
+        I: IntoIterator<Item = String>
+    {
+        let mut config = ExportConfig::default();
+        let (mut opponent, mut format) = (None, None);
+        let mut board_args = vec![];
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+
+            match arg.as_str() {
+                "--engine" => config.engines[0] = value()?.parse()?,
+                "--opponent" => opponent = Some(value()?.parse()?),
+                "--games" => {
+                    let value = value()?;
+                    config.games = value.trim().parse().ok().filter(|&games| games > 0)
+                        .ok_or(format!("invalid number of games '{}'", value))?;
+                }
+                "--noise" => {
+                    let value = value()?;
+                    config.noise = value.trim().parse().ok().filter(|noise| (0.0 ..= 1.0).contains(noise))
+                        .ok_or(format!("invalid noise '{}', expected a probability between 0 and 1", value))?;
+                }
+                "--output" => config.output = value()?,
+                "--format" => format = Some(value()?.parse()?),
+                "--seed" => {
+                    let value = value()?;
+                    config.seed = Some(value.trim().parse().map_err(|_| format!("invalid seed '{}'", value))?);
+                }
+                _ => board_args.push(arg),
+            }
+        }
+
+        config.engines[1] = opponent.unwrap_or_else(|| config.engines[0].clone());
+        config.format = format.or(Format::from_path(&config.output)).unwrap_or_default();
+        config.board = Config::from_args(board_args)?;
+        if config.board.players != 2 {
+            return Err("positions are exported from two-player games".to_string());
+        }
+
+        apply_params(&config.board, &mut config.engines)?;
+
+        Ok(config)
+    }
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        let engine: EngineSpec = "negamax:medium".parse().expect("a valid engine");
+
+        ExportConfig {
+            board: Config::default(),
+            engines: [engine.clone(), engine],
+            games: GAMES,
+            noise: 0.1,
+            output: OUTPUT_FILE.to_string(),
+            format: Format::default(),
+            seed: None,
+        }
+    }
+}
+
+/// Plays a self-play game, with a random move instead of the engine's
+/// one at the configured rate, and returns every position of it (that
+/// isn't among the given keys yet) with the engine's search result
+pub fn play_game(config: &ExportConfig, engines: [&mut dyn Engine; 2], rng: &mut Rng, keys: &mut HashSet<u64>) -> Vec<Record> {
+    let mut board = Board::from_state(config.board.state());
+    let mut records = vec![];
+    let [first, second] = engines;
+
+    while !board.is_over() {
+        let engine = match board.state().player() {
+            Player::Red => &mut *first,
+            _ => &mut *second,
+        };
+
+        let mut last = None::<SearchResult>;
+        engine.set_history(board.history());
+        let Some(mut mv) = engine.choose(board.state(), &mut |result| last = Some(result.clone())) else {
+            break;
+        };
+
+        let state = board.state().clone();
+        let key = state.canonical_hash();
+        if keys.insert(key) {
+            let (score, best) = last.map_or((Score::DRAW, Some(mv)), |result| (result.score, result.best_move));
+            records.push(Record { key, state, score, best, result: 0 });
+        }
+
+        if rng.next_f32() < config.noise {
+            let moves = board.state().get_valid();
+            mv = moves[rng.below(moves.len())];
+        }
+
+        board.play(mv);
+    }
+
+    let winner = board.state().get_winner();
+    for record in records.iter_mut() {
+        record.result = match winner {
+            Some(winner) if winner == record.state.player() => 1,
+            Some(_) => -1,
+            None => 0,
+        };
+    }
+
+    records
+}
+
+/// Returns the records in the CSV format (with a header line)
+pub fn to_csv(records: &[Record]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for record in records {
+        csv.push_str(&record.to_csv());
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Returns the records in the JSONL format
+pub fn to_jsonl(records: &[Record]) -> String {
+    records.iter().map(|record| record.to_json() + "\n").collect()
+}
+
+/// Returns the records of a board of the given size in the binary format:
+/// a header (`MAGIC`, `VERSION`, rows, cols), followed by a fixed-size
+/// record per position, all numbers little endian:
+///
+/// ```text
+/// key     u64     canonical key of the position
+/// cells   2 bits per cell (row by row, 0 = empty, 1 + player index)
+/// player  u8      index of the player to move
+/// score   i32     score of the search (see `Score`)
+/// best    u8      column of the best move (+ 0x80 for a pop, 0xFF if none)
+/// result  i8      result for the player to move
+/// ```
+pub fn to_binary(rows: usize, cols: usize, records: &[Record]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend([VERSION, rows as u8, cols as u8]);
+
+    for record in records {
+        bytes.extend(record.key.to_le_bytes());
+
+        let mut cells = vec![0u8; (rows * cols).div_ceil(4)];
+        for (i, cell) in record.state.data.iter().flatten().enumerate() {
+            let value = cell.player().map_or(0, |player| player.index() as u8 + 1);
+            cells[i / 4] |= value << (2 * (i % 4));
+        }
+        bytes.extend(cells);
+
+        bytes.push(record.state.player().index() as u8);
+        bytes.extend(record.score.0.to_le_bytes());
+        bytes.push(match record.best {
+            Some(Move::Drop(col)) => col as u8,
+            Some(Move::Pop(col)) => col as u8 | 0x80,
+            None => 0xFF,
+        });
+        bytes.push(record.result as u8);
+    }
+
+    bytes
+}
+
+/// Reads records in the binary format (see `to_binary`). The states of
+/// the records are those of a standard game on the given board (i.e.
+/// without the variant, win length or captured pieces).
+pub fn from_binary(bytes: &[u8]) -> Result<Vec<Record>, String> {
+    if bytes.len() < 7 || &bytes[.. 4] != MAGIC || bytes[4] != VERSION {
+        return Err("not a binary dataset (or of an unknown version)".to_string());
+    }
+
+    let (rows, cols) = (bytes[5] as usize, bytes[6] as usize);
+    let cell_bytes = (rows * cols).div_ceil(4);
+    let size = 8 + cell_bytes + 1 + 4 + 1 + 1;
+
+    let body = &bytes[7 ..];
+    if !body.len().is_multiple_of(size) {
+        return Err("truncated binary dataset".to_string());
+    }
+
+    body.chunks(size)
+        .map(|chunk| {
+            let key = u64::from_le_bytes(chunk[.. 8].try_into().expect("8 bytes"));
+
+            let mut state = State::with_size(rows, cols);
+            for i in 0 .. rows * cols {
+                let value = (chunk[8 + i / 4] >> (2 * (i % 4))) & 0b11;
+                if value > 0 {
+                    state[(i / cols, i % cols)] = Cell::Player { player: Player::from_index(value as usize - 1) };
+                }
+            }
+
+            let rest = &chunk[8 + cell_bytes ..];
+            state.turn = Player::from_index(rest[0] as usize);
+
+            let score = Score(i32::from_le_bytes(rest[1 .. 5].try_into().expect("4 bytes")));
+            let best = match rest[5] {
+                0xFF => None,
+                byte if byte & 0x80 != 0 => Some(Move::Pop((byte & 0x7F) as usize)),
+                byte => Some(Move::Drop(byte as usize)),
+            };
+
+            Ok(Record { key, state, score, best, result: rest[6] as i8 })
+        })
+        .collect()
+}
+
+/// Plays the games, alternating the engines' colors, and writes
+/// the (distinct) positions of all games to the output file.
+/// Returns the number of positions written.
+pub fn run(config: &ExportConfig) -> Result<usize, String> {
+    let mut rng = config.seed.map_or_else(Rng::from_time, Rng::new);
+    let mut keys = HashSet::new();
+    let mut records = vec![];
+
+    for game in 0 .. config.games {
+        let (mut a, mut b) = (
+            config.engines[0].create(config.board.threads, Rng::new(rng.next_u64())),
+            config.engines[1].create(config.board.threads, Rng::new(rng.next_u64())),
+        );
+        let engines: [&mut dyn Engine; 2] = match game % 2 {
+            0 => [a.as_mut(), b.as_mut()],
+            _ => [b.as_mut(), a.as_mut()],
+        };
+
+        records.extend(play_game(config, engines, &mut rng, &mut keys));
+        println!("game {}/{}: {} positions", game + 1, config.games, records.len());
+    }
+
+    let bytes = match config.format {
+        Format::Csv => to_csv(&records).into_bytes(),
+        Format::Jsonl => to_jsonl(&records).into_bytes(),
+        Format::Binary => to_binary(config.board.rows, config.board.cols, &records),
+    };
+    fs::write(&config.output, bytes).map_err(|err| format!("cannot write '{}': {}", config.output, err))?;
+
+    Ok(records.len())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn records() -> Vec<Record> {
+        let config = ExportConfig { board: Config { threads: 1, ..Config::default() }, noise: 0.3, engines: ["negamax:easy".parse().unwrap(), "negamax:easy".parse().unwrap()], ..ExportConfig::default() };
+        let (mut a, mut b) = (config.engines[0].create(1, Rng::new(2)), config.engines[1].create(1, Rng::new(3)));
+
+        play_game(&config, [a.as_mut(), b.as_mut()], &mut Rng::new(4), &mut HashSet::new())
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = ExportConfig::from_args(args(&["--engine", "mcts", "--noise", "0.5", "--output", "data.jsonl"])).unwrap();
+
+        assert_eq!(
+            (config.engines[1].engine, config.noise, config.format), (crate::eval::engine::EngineKind::Mcts, 0.5, Format::Jsonl),
+            "Expected the arguments to be parsed, got {:?}",
+            config
+        );
+
+        for invalid in [&["--noise", "2"][..], &["--format", "xml"], &["--games", "0"], &["--players", "3"]] {
+            assert!(
+                ExportConfig::from_args(args(invalid)).is_err(),
+                "Expected {:?} to be rejected, but it wasn't",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that the positions of a game are labeled and distinct
+    fn test_play_game() {
+        let records = records();
+        let keys = records.iter().map(|record| record.key).collect::<HashSet<_>>();
+
+        assert!(
+            !records.is_empty() && keys.len() == records.len(),
+            "Expected distinct positions, got {} of {}",
+            keys.len(), records.len()
+        );
+
+        assert!(
+            records.iter().all(|record| record.best.is_some() && record.state.is_legal(record.best.unwrap())),
+            "Expected a legal best move for every position, but there wasn't",
+        );
+
+        // the results of both players are opposite (or both draws)
+        let results = records.iter().map(|record| (record.state.player(), record.result)).collect::<HashSet<_>>();
+
+        assert!(
+            results.len() <= 2 && results.iter().map(|(_, result)| *result as i32).sum::<i32>() == 0,
+            "Expected opposite results for both players, got {:?}",
+            results
+        );
+    }
+
+    #[test]
+    /// Tests the text formats and that the binary
+    /// format is read back without any changes
+    fn test_formats() {
+        let records = records();
+
+        let csv = to_csv(&records);
+        assert!(
+            csv.starts_with(CSV_HEADER) && csv.lines().count() == records.len() + 1,
+            "Expected a header and a line per position, got {}",
+            csv
+        );
+
+        let jsonl = to_jsonl(&records);
+        assert!(
+            jsonl.lines().all(|line| line.starts_with("{\"key\":") && line.ends_with('}')),
+            "Expected a JSON object per line, got {}",
+            jsonl
+        );
+
+        let read = from_binary(&to_binary(6, 7, &records)).unwrap();
+        assert!(
+            read.len() == records.len() && read.iter().zip(&records).all(|(a, b)| (a.key, &a.state.data, a.state.turn, a.score, a.best, a.result) == (b.key, &b.state.data, b.state.turn, b.score, b.best, b.result)),
+            "Expected the records to be read back, got {:?}",
+            read.first()
+        );
+    }
+}
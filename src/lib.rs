@@ -0,0 +1,13 @@
+pub mod bench;
+pub mod board;
+pub mod config;
+pub mod detect;
+pub mod display;
+pub mod endgame;
+pub mod eval;
+pub mod export;
+pub mod rules;
+pub mod tournament;
+pub mod tune;
+#[cfg(feature = "nn")]
+pub mod train;
@@ -1,57 +1,309 @@
-use std::{sync::{Arc, Mutex}, thread};
+use std::{path::Path, process, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread, time::Duration};
 
-use board::{Board, MAXIMIZER, MINIMIZER};
-use display::{HEIGHT, MARGIN, SIZE, WIDTH};
+use confour::{
+    bench,
+    board::{Board, Move, State, MAXIMIZER},
+    config::Config,
+    detect::threats::threats_all,
+    display::{self, MARGIN, SIZE},
+    endgame::{self, EndgameConfig},
+    eval::{difficulty::Difficulty, engine::Engine, evaluator::Evaluator, random::Rng, score::Score, search::{SearchLimits, SearchResult}, table::{TranspositionTable, CACHE_DEPTH}},
+    export::{self, ExportConfig},
+    tournament::{self, TournamentConfig},
+    tune::{self, TuneConfig},
+};
 
-use macroquad::{input::{is_key_down, is_mouse_button_pressed, mouse_position, KeyCode, MouseButton}, miniquad::window::set_window_size, window::next_frame};
-
-pub mod board;
-pub mod detect;
-pub mod display;
-pub mod eval;
+use macroquad::{input::{is_key_down, is_key_pressed, is_mouse_button_pressed, is_quit_requested, mouse_position, prevent_quit, KeyCode, MouseButton}, miniquad::window::set_window_size, window::next_frame};
 
 pub const DEPTH: usize = 6;
 
-#[macroquad::main("ConFour")]
-async fn main() {
-    set_window_size(WIDTH as u32, HEIGHT as u32);
+/// The analysis overlay, showing the score of every column
+/// on the human's turn (see `State::analyze`)
+#[derive(Default)]
+struct Analysis {
+    /// whether the overlay is shown, toggled with [a]
+    enabled: bool,
+
+    /// the analyzed position and the scores of its moves
+    scores: Option<(State, Vec<(Move, Score)>)>,
+}
+
+/// The engine's assessment of the current position, shown as an
+/// evaluation bar, updated by a background analysis thread
+#[derive(Default)]
+struct Assessment {
+    /// whether the recommended column is highlighted
+    /// on the human's turn, toggled with [h]
+    hint: bool,
+
+    /// whether the threats of every player are
+    /// marked (see `threats_all`), toggled with [t]
+    threats: bool,
+
+    /// the assessed position and the (latest) result of its
+    /// search, scored from the MAXIMIZER's perspective
+    result: Option<(State, SearchResult)>,
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+
+    // headless subcommands, the game otherwise
+    let result = match args.peek().map(String::as_str) {
+        Some("tournament") => TournamentConfig::from_args(args.skip(1))
+            .and_then(|config| config.board.install().and_then(|_| tournament::run(&config)))
+            .map(|_| ()),
+        Some("tune") => TuneConfig::from_args(args.skip(1))
+            .and_then(|config| config.board.install().and_then(|_| tune::run(&config)))
+            .map(|_| ()),
+        Some("export") => ExportConfig::from_args(args.skip(1))
+            .and_then(|config| config.board.install().and_then(|_| export::run(&config)))
+            .map(|positions| println!("{} positions written", positions)),
+        #[cfg(feature = "nn")]
+        Some("train") => confour::train::TrainConfig::from_args(args.skip(1))
+            .and_then(|config| confour::train::run(&config))
+            .map(|_| ()),
+        Some("bench") => run_bench(args.skip(1)),
+        Some("tablebase") => EndgameConfig::from_args(args.skip(1)).and_then(|config| endgame::run(&config)),
+        _ => Config::from_args(args)
+            .and_then(|config| config.install().and_then(|_| config.load_evaluator()).map(|evaluator| (config, evaluator)))
+            .map(|(config, evaluator)| macroquad::Window::new("ConFour", play(config, evaluator))),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(2);
+    }
+}
+
+/// Runs the benchmark (see `bench::run`) to the given
+/// depth, if any, printing the result of every position
+fn run_bench(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let depth = match args.next() {
+        Some(depth) => depth.trim().parse().ok().filter(|&depth| depth > 0).ok_or(format!("invalid depth '{}'", depth))?,
+        None => bench::BENCH_DEPTH,
+    };
 
-    let board = Arc::new(Mutex::new(Board::new()));
+    if let Some(arg) = args.next() {
+        return Err(format!("unknown argument '{}'", arg));
+    }
+
+    let result = bench::run(depth, |name, result| println!("{:<8} {}", name, result));
+    println!("{}", result);
+
+    Ok(())
+}
+
+/// Plays a game against the engine (using the given
+/// evaluator) in a window
+async fn play(config: Config, evaluator: &'static dyn Evaluator) {
+    let state = config.state();
+    set_window_size(display::width(state.cols()) as u32, display::height(state.rows()) as u32);
+
+    let board = Arc::new(Mutex::new(Board::from_state(state)));
 
     let board_eval = Arc::clone(&board);
     let board_draw = Arc::clone(&board);
 
+    // the latest (progress of the) engine's search, if any
+    let info = Arc::new(Mutex::new(None));
+    let info_eval = Arc::clone(&info);
+
+    let analysis = Arc::new(Mutex::new(Analysis::default()));
+    let analysis_eval = Arc::clone(&analysis);
+
+    let assessment = Arc::new(Mutex::new(Assessment::default()));
+    let assessment_eval = Arc::clone(&assessment);
+    let board_assess = Arc::clone(&board);
+
+    let threads = config.threads;
+
+    // selected with the number keys [1] - [6]
+    let difficulty = Arc::new(Mutex::new(config.difficulty));
+    let difficulty_eval = Arc::clone(&difficulty);
+
+    // shared by the engine and the background analysis
+    let table = Arc::new(TranspositionTable::new());
+    load_cache(&table, &config, evaluator);
+
+    let table_assess = Arc::clone(&table);
+    let table_engine = Arc::clone(&table);
+    let table_cache = Arc::clone(&table);
+    let engine_kind = config.engine;
+    let should_ponder = config.ponder;
+
+    // The human plays the MAXIMIZER, the
+    // engine plays every other player
     thread::spawn(move || {
         async_std::task::block_on(async {
-            let mut table = eval::minimax::TranspositionTable::new();
+            let mut engine = engine_kind.create(*difficulty_eval.lock().unwrap(), evaluator, table_engine, threads, Rng::from_time());
+
+            // the last position pondered on (see `Engine::ponder`)
+            let mut pondered = None;
+
             loop {
                 {
                     let should_eval;
+                    let should_analyze;
+                    let state;
                     {
-                        let state = board_eval.lock().unwrap().state().clone();
-                        should_eval = state.player() == MINIMIZER;
+                        let board = board_eval.lock().unwrap();
+                        should_eval = board.state().player() != MAXIMIZER && !board.is_over();
+                        state = (!should_eval && !board.is_over()).then(|| board.state().clone());
+
+                        let analysis = analysis_eval.lock().unwrap();
+                        should_analyze = analysis.enabled && !should_eval && !board.is_over()
+                            && analysis.scores.as_ref().is_none_or(|(state, _)| state != board.state());
+                    }
+
+                    if should_analyze {
+                        analyze(&board, &analysis_eval, evaluator, &table, threads).await;
+                    } else if should_ponder && let Some(state) = state && pondered.as_ref() != Some(&state) {
+                        engine.set_difficulty(*difficulty_eval.lock().unwrap());
+                        engine.set_history(board_eval.lock().unwrap().history());
+                        let (result, done) = ponder(&board_eval, &analysis_eval, engine.as_mut(), &state).await;
+                        if let Some(result) = result {
+                            println!("ponder {}", result);
+                        }
+
+                        // a search interrupted by the analysis
+                        // overlay is started again afterwards
+                        if done {
+                            pondered = Some(state);
+                        }
                     }
     
-                    if should_eval {
-                        let col = eval_omove(&board, &mut table).await;
+                    engine.set_difficulty(*difficulty_eval.lock().unwrap());
+                    if should_eval && let Some(mv) = eval_omove(&board, &info_eval, engine.as_mut()).await {
                         let mut board = board_eval.lock().unwrap();
-                        let state = board.state_mut();
-                        state.drop(col.unwrap(), MINIMIZER);
+
+                        // the game might have been restarted
+                        // while the evaluation was running
+                        if board.state().player() != MAXIMIZER {
+                            board.play(mv);
+                        }
                     }
                 }
             }
         })
     });
 
+    // The background analysis assesses every position
+    // (on a single thread, leaving the others to the engine)
+    thread::spawn(move || {
+        let mut assessed = None;
+        loop {
+            let (state, history, is_over) = {
+                let board = board_assess.lock().unwrap();
+                (board.state().clone(), board.history().to_vec(), board.is_over())
+            };
+
+            if is_over || assessed.as_ref() == Some(&state) {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+
+            // scored from the MAXIMIZER's perspective for the evaluation bar
+            state.search(SearchLimits::depth(DEPTH), MAXIMIZER, evaluator, &table_assess, 1, &history, |result| {
+                assessment_eval.lock().unwrap().result = Some((state.clone(), result.clone()));
+            });
+
+            assessed = Some(state);
+        }
+    });
+
+    // the cache is written when the window is closed
+    if cache_file(&config).is_some() {
+        prevent_quit();
+    }
+
     loop {
-        tick(&board_draw).await;
-        draw(&board_draw).await;
+        if is_quit_requested() {
+            save_cache(&table_cache, &config, evaluator);
+            break;
+        }
+
+        tick(&board_draw, &info, &analysis, &assessment, &difficulty, &config).await;
+        draw(&board_draw, &info, &analysis, &assessment, &difficulty).await;
         next_frame().await;
     }
 }
 
-async fn eval_omove(board: &Mutex<Board>, tt: &mut eval::minimax::TranspositionTable) 
-    -> Option<usize>
+/// Returns the cache file, if any and the variant allows one. Where
+/// positions can repeat (e.g. PopOut), their scores depend on the
+/// moves leading to them, so they can't be kept across games.
+fn cache_file(config: &Config) -> Option<&String> {
+    config.cache.as_ref().filter(|_| !config.state().rules().can_repeat())
+}
+
+/// Loads the entries of the cache file of previous runs, if any, into
+/// the table. A missing or outdated cache, or one of another evaluator,
+/// starts an empty one.
+fn load_cache(table: &TranspositionTable, config: &Config, evaluator: &dyn Evaluator) {
+    if config.cache.is_some() && cache_file(config).is_none() {
+        eprintln!("warning: the cache is not used for {}, as its positions can repeat", config.variant);
+    }
+
+    let Some(path) = cache_file(config) else {
+        return;
+    };
+
+    if !Path::new(path).exists() {
+        return;
+    }
+
+    match table.load(path, &config.state(), evaluator.fingerprint()) {
+        Ok(entries) => println!("{} cached entries loaded from {}", entries, path),
+        Err(err) => eprintln!("warning: {}, starting an empty cache", err),
+    }
+}
+
+/// Writes the deep and decided entries of the table
+/// to the cache file, if any (see `load_cache`)
+fn save_cache(table: &TranspositionTable, config: &Config, evaluator: &dyn Evaluator) {
+    if let Some(path) = cache_file(config) {
+        match table.save(path, CACHE_DEPTH, &config.state(), evaluator.fingerprint()) {
+            Ok(entries) => println!("{} entries written to {}", entries, path),
+            Err(err) => eprintln!("warning: {}", err),
+        }
+    }
+}
+
+/// Lets the engine ponder on the human's turn (see `Engine::ponder`)
+/// until the human moves, the game is restarted or the analysis
+/// overlay needs the engine's thread. Returns the result of the search,
+/// if any, and whether the position is done with, i.e. whether the
+/// search completed or the position changed (but not if it was
+/// interrupted by the analysis overlay).
+async fn ponder(board: &Mutex<Board>, analysis: &Mutex<Analysis>, engine: &mut dyn Engine, state: &State) -> (Option<SearchResult>, bool) {
+    let stop = AtomicBool::new(false);
+    let mut done = true;
+
+    thread::scope(|scope| {
+        let search = scope.spawn(|| engine.ponder(state, &stop));
+
+        while !search.is_finished() {
+            let moved = board.lock().unwrap().state() != state;
+            let analyze = {
+                let analysis = analysis.lock().unwrap();
+                analysis.enabled && analysis.scores.as_ref().is_none_or(|(analyzed, _)| analyzed != state)
+            };
+
+            if moved || analyze {
+                stop.store(true, Ordering::Relaxed);
+                done = moved;
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        (search.join().expect("the ponder search doesn't panic"), done)
+    })
+}
+
+async fn eval_omove(board: &Mutex<Board>, info: &Mutex<Option<SearchResult>>, engine: &mut dyn Engine) 
+    -> Option<Move>
 {
     let state;
     {
@@ -60,65 +312,141 @@ async fn eval_omove(board: &Mutex<Board>, tt: &mut eval::minimax::TranspositionT
         // actual evaluation takes place. This
         // makes board accessible to the main
         // thread while the evaluation is running.
-        state = board.lock().unwrap().state().clone();
+        let board = board.lock().unwrap();
+        state = board.state().clone();
+        engine.set_history(board.history());
     }
 
-    let best = state.best(DEPTH, MINIMIZER, tt);
+    engine.choose(&state, &mut |result| {
+        println!("{}", result);
+        *info.lock().unwrap() = Some(result.clone());
+    })
+}
+
+/// Scores every column of the current position for the analysis overlay
+async fn analyze(board: &Mutex<Board>, analysis: &Mutex<Analysis>, evaluator: &dyn Evaluator, tt: &TranspositionTable, threads: usize) {
+    let (state, history) = {
+        let board = board.lock().unwrap();
+        (board.state().clone(), board.history().to_vec())
+    };
+    let scores = state.analyze(DEPTH, evaluator, tt, threads, &history);
 
-    best
+    analysis.lock().unwrap().scores = Some((state, scores));
 }
 
-async fn tick(board: &Mutex<Board>) {
+async fn tick(board: &Mutex<Board>, info: &Mutex<Option<SearchResult>>, analysis: &Mutex<Analysis>, assessment: &Mutex<Assessment>, difficulty: &Mutex<Difficulty>, config: &Config) {
+    let keys = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5, KeyCode::Key6];
+    for (key, level) in keys.into_iter().zip(Difficulty::ALL) {
+        if is_key_pressed(key) {
+            *difficulty.lock().unwrap() = level;
+        }
+    }
+
+    if is_key_pressed(KeyCode::A) {
+        let mut analysis = analysis.lock().unwrap();
+        analysis.enabled = !analysis.enabled;
+    }
+
+    if is_key_pressed(KeyCode::H) {
+        let mut assessment = assessment.lock().unwrap();
+        assessment.hint = !assessment.hint;
+    }
+
+    if is_key_pressed(KeyCode::T) {
+        let mut assessment = assessment.lock().unwrap();
+        assessment.threats = !assessment.threats;
+    }
+
     let mut board = board.lock().unwrap();
-    let state = board.state_mut();
 
-    if state.get_winner().is_some() || state.is_full() {
+    if board.is_over() {
         if is_key_down(KeyCode::R) {
-            *board = Board::new();
+            *board = Board::from_state(config.state());
+            *info.lock().unwrap() = None;
         }
         return;
     }
 
+    if board.state().player() != MAXIMIZER {
+        return;
+    }
+
+    let Some(col) = mouse_column(board.state().cols()) else {
+        return;
+    };
+
+    // left click drops a piece, right click pops one (PopOut)
     if is_mouse_button_pressed(MouseButton::Left) {
-        if let Some(col) = mouse_column() {
-            let player = state.player();
-            if player == MAXIMIZER {
-                state.drop(col, player);
-            }
-        }
+        board.play(Move::Drop(col));
+    } else if is_mouse_button_pressed(MouseButton::Right) {
+        board.play(Move::Pop(col));
     }
 }
 
-async fn draw(board: &Mutex<Board>) {
+async fn draw(board: &Mutex<Board>, info: &Mutex<Option<SearchResult>>, analysis: &Mutex<Analysis>, assessment: &Mutex<Assessment>, difficulty: &Mutex<Difficulty>) {
     let state;
+    let is_over;
     {
-        state = board.lock().unwrap().state().clone();
+        let board = board.lock().unwrap();
+        state = board.state().clone();
+        is_over = board.is_over();
     }
 
-    if state.is_terminal() {
+    let info = info.lock().unwrap().clone();
+    let difficulty = *difficulty.lock().unwrap();
+
+    if is_over {
         let winner = state.get_winner();
         display::draw_game_over(&state, winner).await;
 
         return;
     }
 
-    let col = mouse_column();
-    if let Some(col) = col {
-        if state.player() == MAXIMIZER {
-            display::draw_highlight(&state, col).await;
-        }
+    // the assessment of the current position, if it was assessed yet
+    let (hint, threats, assessed) = {
+        let assessment = assessment.lock().unwrap();
+        let result = assessment.result.clone().filter(|(assessed, _)| *assessed == state);
+        (assessment.hint, assessment.threats, result.map(|(_, result)| result))
+    };
+
+    if hint && state.player() == MAXIMIZER && let Some(mv) = assessed.as_ref().and_then(|result| result.best_move) {
+        display::draw_hint(&state, mv.col()).await;
+    }
+
+    let col = mouse_column(state.cols());
+    if let Some(col) = col && state.player() == MAXIMIZER {
+        display::draw_highlight(&state, col).await;
     }
 
     display::draw_board(&state).await;
+
+    if threats {
+        display::draw_threats(&threats_all(&state)).await;
+    }
+
+    if let Some(result) = assessed {
+        display::draw_eval_bar(&state, result.score).await;
+    }
+
+    display::draw_status(difficulty, info.as_ref()).await;
+
+    let scores = {
+        let analysis = analysis.lock().unwrap();
+        analysis.scores.clone().filter(|(analyzed, _)| analysis.enabled && *analyzed == state)
+    };
+
+    if let Some((_, scores)) = scores {
+        display::draw_analysis(&state, &scores).await;
+    }
 }
 
-fn mouse_column() -> Option<usize> {
+fn mouse_column(cols: usize) -> Option<usize> {
     let (x, _) = mouse_position();
     let col = ((x - MARGIN) / SIZE) as usize;
     
     match x {
         x if x < MARGIN => return None,
-        x if x > WIDTH - MARGIN => return None,
+        x if x >= display::width(cols) - MARGIN => return None,
         _ => (),
     }
 
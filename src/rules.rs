@@ -0,0 +1,469 @@
+use std::{fmt, str::FromStr};
+
+use crate::{board::{Cell, Move, Player, State}, detect::sequences_from_position};
+
+/// The number of pieces a player has to collect to win "Pop 10"
+pub const POP_TEN_TARGET: u8 = 10;
+
+/// The rules of a variant of connect four. The rules build
+/// on the primitive operations of the State struct (dropping,
+/// popping, detecting sequences) and define which moves are
+/// legal, what they do and who wins.
+pub trait Rules: Sync {
+    /// Returns the (display) name of the variant
+    fn name(&self) -> &'static str;
+
+    /// Prepares the initial state of a game, e.g. by
+    /// changing the board size or pre-filling cells
+    fn setup(&self, state: State) -> State {
+        state
+    }
+
+    /// Returns all legal moves of the current player
+    fn moves(&self, state: &State) -> Vec<Move>;
+
+    /// Plays the given (legal) move for the current player
+    fn play(&self, state: &mut State, mv: Move);
+
+    /// Returns the winner of the game, if any
+    fn winner(&self, state: &State) -> Option<Player>;
+
+    /// Returns whether the current player has any legal move,
+    /// variants override this to avoid collecting the moves
+    fn has_moves(&self, state: &State) -> bool {
+        !self.moves(state).is_empty()
+    }
+
+    /// Returns whether the given (legal) move wins the game
+    /// for the current player. Variants won by connecting
+    /// dropped pieces only need to check the new piece.
+    fn wins_with(&self, state: &State, mv: Move) -> bool {
+        state.played(mv).get_winner() == Some(state.player())
+    }
+
+    /// Returns whether a position may occur more than once
+    /// in a game (i.e. whether repetitions need to be checked)
+    fn can_repeat(&self) -> bool {
+        false
+    }
+}
+
+/// The standard rules: drop pieces, connect `win_length` to win
+pub struct Standard;
+
+/// The PopOut rules: like the standard rules, but instead of
+/// dropping a piece, players may pop one of their own pieces
+/// from the bottom of a column
+pub struct PopOut;
+
+/// The "5-in-a-Row" rules: a 9x6 board whose outer columns are
+/// pre-filled with alternating colors, five in a row are needed
+/// to win
+pub struct FiveInARow;
+
+/// The "Pop 10" rules: the board is filled up first, after that
+/// players pop their own pieces from the bottom row. A popped
+/// piece that was part of a connected line is kept, any other
+/// one is put back on top of its column. The first player to
+/// collect `POP_TEN_TARGET` pieces wins.
+pub struct PopTen;
+
+/// Returns all columns a piece can be dropped into
+fn drops(state: &State) -> impl Iterator<Item = Move> + '_ {
+    (0 .. state.cols()).filter(|&col| state.is_valid(col)).map(Move::Drop)
+}
+
+/// Returns all columns the current player can pop a piece from
+fn pops(state: &State) -> impl Iterator<Item = Move> + '_ {
+    (0 .. state.cols()).filter(|&col| state.can_pop(col)).map(Move::Pop)
+}
+
+/// Returns the player who connected `win_length` pieces. If several
+/// players connected at once (which can only happen by popping),
+/// the player who made the last move wins if they connected, else
+/// the first connected player in turn order.
+fn connected_winner(state: &State) -> Option<Player> {
+    // a drop only completes lines through the dropped piece
+    if let Some((row, col)) = state.last {
+        return state[(row, col)].player().filter(|_| state.connects_at(row, col));
+    }
+
+    let players = state.players;
+    let mover = state.player().previous(players);
+
+    (0 .. players)
+        .map(|offset| Player::from_index((mover.index() + offset) % players))
+        .find(|&player| state.has_connected(player))
+}
+
+impl Rules for Standard {
+    fn name(&self) -> &'static str {
+        "Standard"
+    }
+
+    fn moves(&self, state: &State) -> Vec<Move> {
+        drops(state).collect()
+    }
+
+    fn has_moves(&self, state: &State) -> bool {
+        !state.is_full()
+    }
+
+    fn play(&self, state: &mut State, mv: Move) {
+        if let Move::Drop(col) = mv {
+            state.drop(col, state.player());
+        }
+    }
+
+    fn winner(&self, state: &State) -> Option<Player> {
+        connected_winner(state)
+    }
+
+    fn wins_with(&self, state: &State, mv: Move) -> bool {
+        matches!(mv, Move::Drop(col) if state.wins_after(col))
+    }
+}
+
+impl Rules for PopOut {
+    fn name(&self) -> &'static str {
+        "PopOut"
+    }
+
+    fn moves(&self, state: &State) -> Vec<Move> {
+        drops(state).chain(pops(state)).collect()
+    }
+
+    fn has_moves(&self, state: &State) -> bool {
+        !state.is_full() || pops(state).next().is_some()
+    }
+
+    fn play(&self, state: &mut State, mv: Move) {
+        match mv {
+            Move::Drop(col) => state.drop(col, state.player()),
+            Move::Pop(col) => state.pop(col, state.player()),
+        }
+    }
+
+    fn winner(&self, state: &State) -> Option<Player> {
+        connected_winner(state)
+    }
+
+    fn can_repeat(&self) -> bool {
+        true
+    }
+}
+
+impl Rules for FiveInARow {
+    fn name(&self) -> &'static str {
+        "5-in-a-Row"
+    }
+
+    fn setup(&self, _state: State) -> State {
+        let mut state = State::with_size(6, 9)
+            .with_win_length(5);
+        state.variant = Variant::FiveInARow;
+
+        let (rows, cols) = (state.rows(), state.cols());
+        for row in 0 .. rows {
+            // alternate colors from the bottom up, with the
+            // right column being the "inverse" of the left one
+            let player = if (rows - 1 - row).is_multiple_of(2) { Player::Red } else { Player::Yellow };
+
+            state[(row, 0)] = Cell::Player { player };
+            state[(row, cols - 1)] = Cell::Player { player: player.other() };
+        }
+
+        state
+    }
+
+    fn moves(&self, state: &State) -> Vec<Move> {
+        Standard.moves(state)
+    }
+
+    fn has_moves(&self, state: &State) -> bool {
+        Standard.has_moves(state)
+    }
+
+    fn play(&self, state: &mut State, mv: Move) {
+        Standard.play(state, mv)
+    }
+
+    fn winner(&self, state: &State) -> Option<Player> {
+        connected_winner(state)
+    }
+
+    fn wins_with(&self, state: &State, mv: Move) -> bool {
+        Standard.wins_with(state, mv)
+    }
+}
+
+impl PopTen {
+    /// Returns whether the filling phase is over,
+    /// i.e. whether pieces are popped instead of dropped
+    pub fn is_popping(state: &State) -> bool {
+        state.is_full() || state.captured.iter().any(|&n| n > 0)
+    }
+}
+
+impl Rules for PopTen {
+    fn name(&self) -> &'static str {
+        "Pop 10"
+    }
+
+    fn moves(&self, state: &State) -> Vec<Move> {
+        if PopTen::is_popping(state) {
+            pops(state).collect()
+        } else {
+            drops(state).collect()
+        }
+    }
+
+    fn has_moves(&self, state: &State) -> bool {
+        if PopTen::is_popping(state) {
+            pops(state).next().is_some()
+        } else {
+            drops(state).next().is_some()
+        }
+    }
+
+    fn play(&self, state: &mut State, mv: Move) {
+        let player = state.player();
+
+        match mv {
+            Move::Drop(col) => state.drop(col, player),
+            Move::Pop(col) => {
+                let bottom = state.rows() - 1;
+                let connected = sequences_from_position(state, player, bottom, col)
+                    .iter()
+                    .any(|seq| seq.len() >= state.win_length);
+
+                state.pop(col, player);
+
+                if connected {
+                    state.captured[player.index()] += 1;
+                } else {
+                    state[(0, col)] = Cell::Player { player };
+                }
+            }
+        }
+    }
+
+    fn winner(&self, state: &State) -> Option<Player> {
+        Player::ALL[.. state.players]
+            .iter()
+            .copied()
+            .find(|player| state.captured[player.index()] >= POP_TEN_TARGET)
+    }
+
+    fn can_repeat(&self) -> bool {
+        true
+    }
+}
+
+/// All supported variants (rule sets) of the game
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Default)]
+pub enum Variant {
+    #[default]
+    Standard,
+    PopOut,
+    FiveInARow,
+    PopTen,
+}
+
+impl Variant {
+    /// All supported variants, e.g. for listing them
+    pub const ALL: [Variant; 4] = [Variant::Standard, Variant::PopOut, Variant::FiveInARow, Variant::PopTen];
+
+    /// Returns the rules of the variant
+    pub fn rules(&self) -> &'static dyn Rules {
+        match self {
+            Variant::Standard => &Standard,
+            Variant::PopOut => &PopOut,
+            Variant::FiveInARow => &FiveInARow,
+            Variant::PopTen => &PopTen,
+        }
+    }
+
+    /// Returns the name used to select the
+    /// variant on the command line
+    pub fn key(&self) -> &'static str {
+        match self {
+            Variant::Standard => "standard",
+            Variant::PopOut => "popout",
+            Variant::FiveInARow => "five",
+            Variant::PopTen => "pop10",
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    /// Display the name of the variant (e.g. "Pop 10")
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.rules().name())
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    /// Parse a variant from its command line name (see `key`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Variant::ALL
+            .into_iter()
+            .find(|variant| variant.key() == s.trim())
+            .ok_or_else(|| {
+                let keys = Variant::ALL.map(|variant| variant.key()).join(", ");
+                format!("unknown variant '{}', expected one of: {}", s, keys)
+            })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests the pre-filled edge columns of "5-in-a-Row"
+    fn test_five_in_a_row_setup() {
+        let state = State::new().with_variant(Variant::FiveInARow);
+
+        assert_eq!(
+            (state.rows(), state.cols(), state.win_length), (6, 9, 5),
+            "Expected a 9x6 board needing five in a row, but it wasn't",
+        );
+
+        assert_eq!(
+            state.get_valid(), (1 .. 8).map(Move::Drop).collect::<Vec<_>>(),
+            "Expected only the inner columns to be valid, got {:?}",
+            state.get_valid(),
+        );
+
+        assert_eq!(
+            state.player(), Player::Red,
+            "Expected player 1 to start, but it didn't",
+        );
+
+        for row in 0 .. state.rows() {
+            assert!(
+                state[(row, 0)] != state[(row, 8)] && !state[(row, 0)].is_empty(),
+                "Expected the edge columns to be filled with opposite colors in row {}",
+                row,
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that five (and not four) in a row
+    /// win a game of "5-in-a-Row"
+    fn test_five_in_a_row_winner() {
+        let mut state = State::new().with_variant(Variant::FiveInARow);
+
+        // the bottom left edge cell is red, so four
+        // more red pieces make five in a row
+        for col in 1 .. 4 {
+            state[(5, col)] = Cell::Player { player: Player::Red };
+        }
+
+        assert_eq!(
+            state.get_winner(), None,
+            "Expected no winner with four in a row, got {:?}",
+            state.get_winner(),
+        );
+
+        state[(5, 4)] = Cell::Player { player: Player::Red };
+
+        assert_eq!(
+            state.get_winner(), Some(Player::Red),
+            "Expected winner to be Red, got {:?}",
+            state.get_winner(),
+        );
+    }
+
+    #[test]
+    /// Tests both kinds of pops in "Pop 10": keeping
+    /// a connected piece and putting back any other one
+    fn test_pop_ten_pop() {
+        let mut state = State::with_size(4, 4).with_variant(Variant::PopTen);
+
+        // bottom row: R R R y, everything above alternates
+        let bottom = [Player::Red, Player::Red, Player::Red, Player::Yellow];
+        for col in 0 .. 4 {
+            state[(3, col)] = Cell::Player { player: bottom[col] };
+            for row in 0 .. 3 {
+                let player = if (row + col).is_multiple_of(2) { Player::Red } else { Player::Yellow };
+                state[(row, col)] = Cell::Player { player };
+            }
+        }
+        state.win_length = 3;
+
+        assert_eq!(
+            state.get_valid(), vec![Move::Pop(0), Move::Pop(1), Move::Pop(2)],
+            "Expected red to only be able to pop its own pieces, got {:?}",
+            state.get_valid(),
+        );
+
+        state.play(Move::Pop(0));
+
+        assert_eq!(
+            state.captured, [1, 0, 0],
+            "Expected red to keep the connected piece, got {:?}",
+            state.captured,
+        );
+
+        assert!(
+            state[(0, 0)].is_empty(),
+            "Expected the column to have a free cell on top, but it didn't",
+        );
+
+        // yellow's bottom piece is not part of a line of three
+        state.play(Move::Pop(3));
+
+        assert_eq!(
+            state.captured, [1, 0, 0],
+            "Expected yellow not to keep the unconnected piece, got {:?}",
+            state.captured,
+        );
+
+        assert_eq!(
+            state[(0, 3)], Cell::Player { player: Player::Yellow },
+            "Expected the unconnected piece to be put back on top, but it wasn't",
+        );
+    }
+
+    #[test]
+    /// Tests that collecting ten pieces wins "Pop 10"
+    fn test_pop_ten_winner() {
+        let mut state = State::new().with_variant(Variant::PopTen);
+        state.captured = [9, POP_TEN_TARGET - 1, 0];
+
+        assert_eq!(
+            state.get_winner(), None,
+            "Expected no winner, got {:?}",
+            state.get_winner(),
+        );
+
+        state.captured[Player::Yellow.index()] += 1;
+
+        assert_eq!(
+            state.get_winner(), Some(Player::Yellow),
+            "Expected winner to be Yellow, got {:?}",
+            state.get_winner(),
+        );
+    }
+
+    #[test]
+    fn test_variant_from_str() {
+        for variant in Variant::ALL {
+            assert_eq!(
+                variant.key().parse::<Variant>(), Ok(variant),
+                "Expected {} to be parsed as {:?}, but it wasn't",
+                variant.key(), variant,
+            );
+        }
+
+        assert!(
+            "bogus".parse::<Variant>().is_err(),
+            "Expected an unknown variant to be rejected, but it wasn't",
+        );
+    }
+}
@@ -0,0 +1,660 @@
+use std::{fmt, fs, io::Write, str::FromStr, sync::Arc};
+
+use crate::{
+    board::{Board, Move, Player},
+    config::Config,
+    eval::{difficulty::Difficulty, engine::{Engine, EngineKind}, evaluator::{Evaluator, EvaluatorKind, Weights}, params::Params, random::Rng, table::TranspositionTable},
+};
+
+/// The default number of games of a tournament
+pub const GAMES: usize = 100;
+
+/// The default file the games of a tournament are written to
+pub const RECORD_FILE: &str = "tournament.txt";
+
+/// An engine taking part in a tournament, written as
+/// "<engine>[:<difficulty>[:<eval>[@<params>]]]", e.g. "mcts:expert",
+/// "negamax:hard:threat" or "negamax:hard:weights@params.txt"
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct EngineSpec {
+    pub engine: EngineKind,
+    pub difficulty: Difficulty,
+    pub evaluator: EvaluatorKind,
+
+    /// the parameter file of the weights evaluation and
+    /// the evaluator using its parameters, if any
+    pub params: Option<(String, &'static Weights)>,
+}
+
+impl EngineSpec {
+    /// Makes the engine's weights evaluation use the
+    /// parameters of the given parameter file
+    pub fn with_params(mut self, path: &str) -> Result<EngineSpec, String> {
+        if !self.evaluator.takes_params() {
+            return Err(format!("the {} evaluator takes no parameters, only the weights evaluator does", self.evaluator));
+        }
+
+        self.params = Some((path.to_string(), Weights::leak(Params::load(path)?)));
+        Ok(self)
+    }
+
+    /// Returns the evaluator of the engine
+    pub fn evaluator(&self) -> &'static dyn Evaluator {
+        match self.params {
+            Some((_, weights)) => weights,
+            None => self.evaluator.evaluator(),
+        }
+    }
+
+    /// Returns a new engine of this configuration, making
+    /// its random choices with the given generator
+    pub fn create(&self, threads: usize, rng: Rng) -> Box<dyn Engine> {
+        let tt = Arc::new(TranspositionTable::new());
+        self.engine.create(self.difficulty, self.evaluator(), tt, threads, rng)
+    }
+}
+
+impl fmt::Display for EngineSpec {
+    /// Display the engine in the form it is parsed from
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.engine.key(), self.difficulty.key(), self.evaluator.key())?;
+
+        match &self.params {
+            Some((path, _)) => write!(f, "@{}", path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for EngineSpec {
+    type Err = String;
+
+    /// Parse an engine of the form "<engine>[:<difficulty>[:<eval>[@<params>]]]",
+    /// leaving out parts resulting in their defaults, reading the
+    /// parameters of the weights evaluation from the parameter file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(':');
+        let mut spec = EngineSpec::default();
+
+        if let Some(engine) = parts.next() {
+            spec.engine = engine.parse()?;
+        }
+        if let Some(difficulty) = parts.next() {
+            spec.difficulty = difficulty.parse()?;
+        }
+        if let Some(evaluator) = parts.next() {
+            let (evaluator, params) = evaluator.split_once('@').map_or((evaluator, None), |(evaluator, params)| (evaluator, Some(params)));
+            spec.evaluator = evaluator.parse()?;
+
+            if let Some(path) = params {
+                spec = spec.with_params(path)?;
+            }
+        }
+        if parts.next().is_some() {
+            return Err(format!("invalid engine '{}', expected <engine>[:<difficulty>[:<eval>[@<params>]]]", s));
+        }
+
+        Ok(spec)
+    }
+}
+
+/// The configuration of a tournament between two engines
+#[derive(Clone, Debug, PartialEq)]
+pub struct TournamentConfig {
+    /// the board (and rules) the games are played on
+    pub board: Config,
+
+    /// the engine tested and the one it is compared to
+    pub engines: [EngineSpec; 2],
+
+    pub games: usize,
+
+    /// the file with the openings to start the games with, if any
+    pub openings: Option<String>,
+
+    /// the file all games are written to
+    pub record: String,
+
+    /// the seed of the opening selection and the engines' random choices
+    pub seed: Option<u64>,
+
+    /// the Elo differences of the null (elo0) and the alternative
+    /// (elo1) hypothesis of the sequential probability ratio test
+    pub elo0: f64,
+    pub elo1: f64,
+}
+
+impl TournamentConfig {
+    /// Parses the configuration from the given command line
+    /// arguments (excluding the program name and the subcommand)
+    ///
+    /// Supported arguments (in addition to the board arguments of `Config`):
+    ///     --engine <spec>         the engine tested (default: negamax),
+    ///                             e.g. "mcts:expert" (see `EngineSpec`)
+    ///     --opponent <spec>       the engine it is compared to (default: negamax)
+    ///     --games <n>             number of games (default: 100)
+    ///     --openings <file>       openings in move notation, one per line
+    ///     --record <file>         file to write the games to (default: tournament.txt)
+    ///     --seed <n>              seed of the openings and the engines' random choices
+    ///     --sprt <elo0>,<elo1>    hypotheses of the SPRT (default: 0,10)
+    pub fn from_args<I>(args: I) -> Result<TournamentConfig, String>
+    where
+        I: IntoIterator<Item = String>
+    {
+        let mut config = TournamentConfig::default();
+        let mut board_args = vec![];
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+
+            match arg.as_str() {
+                "--engine" => config.engines[0] = value()?.parse()?,
+                "--opponent" => config.engines[1] = value()?.parse()?,
+                "--games" => {
+                    let value = value()?;
+                    config.games = value.trim().parse().ok().filter(|&games| games > 0)
+                        .ok_or(format!("invalid number of games '{}'", value))?;
+                }
+                "--openings" => config.openings = Some(value()?),
+                "--record" => config.record = value()?,
+                "--seed" => {
+                    let value = value()?;
+                    config.seed = Some(value.trim().parse().map_err(|_| format!("invalid seed '{}'", value))?);
+                }
+                "--sprt" => (config.elo0, config.elo1) = parse_sprt(&value()?)?,
+                "--difficulty" | "--eval" => {
+                    return Err(format!("{} doesn't apply to tournaments, it is part of the engines, e.g. --engine negamax:hard:threat", arg));
+                }
+                "--cache" | "--no-ponder" => return Err(format!("{} doesn't apply to tournaments", arg)),
+                _ => board_args.push(arg),
+            }
+        }
+
+        config.board = Config::from_args(board_args)?;
+        if config.board.players != 2 {
+            return Err("tournaments are played between two players".to_string());
+        }
+
+        apply_params(&config.board, &mut config.engines)?;
+
+        Ok(config)
+    }
+}
+
+impl Default for TournamentConfig {
+    fn default() -> Self {
+        TournamentConfig {
+            board: Config::default(),
+            engines: Default::default(),
+            games: GAMES,
+            openings: None,
+            record: RECORD_FILE.to_string(),
+            seed: None,
+            elo0: 0.0,
+            elo1: 10.0,
+        }
+    }
+}
+
+/// Makes the given engines whose weights evaluation has no parameter
+/// file of its own use the one given with `--params`, if any. Warns
+/// if none of the engines uses the weights evaluation.
+pub fn apply_params(config: &Config, engines: &mut [EngineSpec]) -> Result<(), String> {
+    let Some(path) = &config.params else {
+        return Ok(());
+    };
+
+    let mut applied = false;
+    for engine in engines.iter_mut().filter(|engine| engine.evaluator.takes_params()) {
+        if engine.params.is_none() {
+            *engine = engine.clone().with_params(path)?;
+        }
+        applied = true;
+    }
+
+    if !applied {
+        eprintln!("warning: --params only affects the weights evaluator, which no engine uses (e.g. negamax:hard:weights)");
+    }
+
+    Ok(())
+}
+
+/// Parses the hypotheses of the SPRT in the form "<elo0>,<elo1>"
+pub fn parse_sprt(value: &str) -> Result<(f64, f64), String> {
+    let parsed = value
+        .split_once(',')
+        .and_then(|(elo0, elo1)| Some((elo0.trim().parse::<f64>().ok()?, elo1.trim().parse::<f64>().ok()?)));
+
+    match parsed {
+        Some((elo0, elo1)) if elo0 < elo1 => Ok((elo0, elo1)),
+        _ => Err(format!("invalid SPRT bounds '{}', expected <elo0>,<elo1> with elo0 < elo1", value)),
+    }
+}
+
+/// Parses openings in move notation, one per line (e.g. "4 4 3"),
+/// skipping empty lines and comments starting with '#'
+pub fn parse_openings(text: &str) -> Result<Vec<Vec<Move>>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.split_whitespace().map(str::parse).collect())
+        .collect()
+}
+
+/// The outcome of an SPRT (sequential probability ratio test)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Sprt {
+    /// the engine is stronger by (about) elo1
+    Pass,
+
+    /// the engine is not stronger by more than (about) elo0
+    Fail,
+
+    /// more games are needed to tell
+    Continue,
+}
+
+/// The false positive and false negative rate of the SPRT
+pub const SPRT_ERROR: f64 = 0.05;
+
+/// The wins, draws and losses of an engine
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Standings {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl Standings {
+    /// Returns the number of games played
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Returns the average score per game (1 for a win, 0.5 for a draw)
+    pub fn score(&self) -> f64 {
+        match self.games() {
+            0 => 0.5,
+            games => (self.wins as f64 + self.draws as f64 / 2.0) / games as f64,
+        }
+    }
+
+    /// Returns the variance of the score of a single game
+    fn variance(&self) -> f64 {
+        let (games, score) = (self.games().max(1) as f64, self.score());
+
+        (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2)) / games
+    }
+
+    /// Returns the Elo difference to the opponent
+    pub fn elo(&self) -> f64 {
+        elo(self.score())
+    }
+
+    /// Returns the margin of error of the Elo difference (95% confidence)
+    pub fn elo_error(&self) -> f64 {
+        let error = 1.96 * (self.variance() / self.games().max(1) as f64).sqrt();
+        let score = self.score();
+
+        (elo(score + error) - elo(score - error)) / 2.0
+    }
+
+    /// Returns the log-likelihood ratio of the Elo difference being
+    /// elo1 rather than elo0 (a normal approximation of the GSPRT)
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance();
+        if variance <= 0.0 {
+            return 0.0;
+        }
+
+        let (s0, s1) = (expected_score(elo0), expected_score(elo1));
+        self.games() as f64 * (s1 - s0) * (2.0 * self.score() - s0 - s1) / (2.0 * variance)
+    }
+
+    /// Returns the outcome of the SPRT of the given hypotheses,
+    /// with false positive and negative rates of `SPRT_ERROR`
+    pub fn sprt(&self, elo0: f64, elo1: f64) -> Sprt {
+        let llr = self.llr(elo0, elo1);
+        let bound = ((1.0 - SPRT_ERROR) / SPRT_ERROR).ln();
+
+        match llr {
+            llr if llr >= bound => Sprt::Pass,
+            llr if llr <= -bound => Sprt::Fail,
+            _ => Sprt::Continue,
+        }
+    }
+}
+
+impl fmt::Display for Standings {
+    /// Display the standings, e.g. "W 12 D 3 L 5 (65.0%) Elo +107.5 +/- 85.2"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "W {} D {} L {} ({:.1}%) Elo {:+.1} +/- {:.1}",
+            self.wins, self.draws, self.losses, self.score() * 100.0, self.elo(), self.elo_error()
+        )
+    }
+}
+
+/// Returns the Elo difference resulting in the given average score
+fn elo(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1.0 - 1e-6);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Returns the average score resulting from the given Elo difference
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Plays a single game between the given engines (the first one playing
+/// the MAXIMIZER), starting with the given opening. Returns the finished
+/// game and its winner (`None` for a draw).
+pub fn play_game(config: &Config, opening: &[Move], engines: [&mut dyn Engine; 2]) -> Result<(Board, Option<Player>), String> {
+    let mut board = Board::from_state(config.state());
+    for &mv in opening {
+        if !board.play(mv) {
+            return Err(format!("illegal move '{}' in opening '{}'", mv, board.notation()));
+        }
+    }
+
+    let [first, second] = engines;
+    while !board.is_over() {
+        let engine = match board.state().player() {
+            Player::Red => &mut *first,
+            _ => &mut *second,
+        };
+
+        engine.set_history(board.history());
+        let Some(mv) = engine.choose(board.state(), &mut |_| ()) else {
+            break;
+        };
+
+        board.play(mv);
+    }
+
+    let winner = board.state().get_winner();
+    Ok((board, winner))
+}
+
+/// Plays a tournament, i.e. pairs of games between the two engines with
+/// alternating colors, starting both games of a pair with the same
+/// (random) opening. Every game is written to the record file and the
+/// standings are printed after each pair, stopping early once the SPRT
+/// passes or fails. Returns the standings of the first engine.
+pub fn run(config: &TournamentConfig) -> Result<Standings, String> {
+    let openings = match &config.openings {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|err| format!("cannot read '{}': {}", path, err))?;
+            parse_openings(&text)?
+        }
+        None => vec![vec![]],
+    };
+    if openings.is_empty() {
+        return Err("no openings to play".to_string());
+    }
+
+    let mut record = fs::File::create(&config.record)
+        .map_err(|err| format!("cannot create '{}': {}", config.record, err))?;
+
+    let mut rng = config.seed.map_or_else(Rng::from_time, Rng::new);
+    let mut standings = Standings::default();
+    let [engine, opponent] = &config.engines;
+
+    println!("{} vs {}, {} games", engine, opponent, config.games);
+
+    let mut opening = &openings[0];
+    for game in 0 .. config.games {
+        // both games of a pair share their opening
+        let swapped = game % 2 == 1;
+        if !swapped {
+            opening = &openings[rng.below(openings.len())];
+        }
+
+        let (mut a, mut b) = (
+            engine.create(config.board.threads, Rng::new(rng.next_u64())),
+            opponent.create(config.board.threads, Rng::new(rng.next_u64())),
+        );
+        let engines: [&mut dyn Engine; 2] = match swapped {
+            false => [a.as_mut(), b.as_mut()],
+            true => [b.as_mut(), a.as_mut()],
+        };
+
+        let (board, winner) = play_game(&config.board, opening, engines)?;
+        let engine_player = if swapped { Player::Yellow } else { Player::Red };
+
+        match winner {
+            Some(player) if player == engine_player => standings.wins += 1,
+            Some(_) => standings.losses += 1,
+            None => standings.draws += 1,
+        }
+
+        let players = if swapped { [opponent, engine] } else { [engine, opponent] };
+        write!(record, "{}", game_record(&config.board, players, opening, &board, winner))
+            .map_err(|err| format!("cannot write '{}': {}", config.record, err))?;
+
+        let sprt = standings.sprt(config.elo0, config.elo1);
+        println!("game {}/{}: {}  LLR {:.2}  SPRT {:?}", game + 1, config.games, standings, standings.llr(config.elo0, config.elo1), sprt);
+
+        if swapped && sprt != Sprt::Continue {
+            break;
+        }
+    }
+
+    Ok(standings)
+}
+
+/// Returns the record of a finished game, i.e. its settings and result as
+/// tags, followed by its moves in move notation and an empty line, e.g.
+///
+/// ```text
+/// [Red "negamax:hard:window"]
+/// [Yellow "mcts:hard:window"]
+/// ...
+/// [Result "1-0"]
+/// 4 4 3 3 2 2 1
+/// ```
+pub fn game_record(config: &Config, players: [&EngineSpec; 2], opening: &[Move], board: &Board, winner: Option<Player>) -> String {
+    let result = match winner {
+        Some(Player::Red) => "1-0",
+        Some(_) => "0-1",
+        None => "1/2-1/2",
+    };
+    let opening = opening.iter().map(|mv| mv.to_string()).collect::<Vec<_>>().join(" ");
+
+    let tags = [
+        ("Red", players[0].to_string()),
+        ("Yellow", players[1].to_string()),
+        ("Variant", config.variant.key().to_string()),
+        ("Size", format!("{}x{}", config.cols, config.rows)),
+        ("Connect", config.win_length.to_string()),
+        ("Cylinder", config.cylinder.to_string()),
+        ("Opening", opening),
+        ("Result", result.to_string()),
+    ];
+
+    let mut record = String::new();
+    for (tag, value) in tags {
+        record.push_str(&format!("[{} \"{}\"]\n", tag, value));
+    }
+    record.push_str(&format!("{}\n\n", board.notation()));
+
+    record
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_engine_spec_from_str() {
+        let spec = "mcts:easy".parse::<EngineSpec>();
+        let expected = EngineSpec { engine: EngineKind::Mcts, difficulty: Difficulty::Easy, evaluator: EvaluatorKind::default(), params: None };
+
+        assert_eq!(
+            spec, Ok(expected.clone()),
+            "Expected {:?}, got {:?}",
+            expected, spec
+        );
+
+        assert_eq!(
+            expected.to_string().parse::<EngineSpec>(), Ok(expected.clone()),
+            "Expected {} to be parsed back, but it wasn't",
+            expected
+        );
+
+        for invalid in ["bogus", "mcts:bogus", "negamax:hard:window:extra"] {
+            assert!(
+                invalid.parse::<EngineSpec>().is_err(),
+                "Expected {:?} to be rejected, but it wasn't",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that the weights evaluation of an engine
+    /// uses the parameters of its parameter file
+    fn test_engine_spec_params() {
+        let params = Params { position: 0.5, ..Params::default() };
+        let path = std::env::temp_dir().join(format!("confour-params-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        params.save(path).unwrap();
+
+        let spec = format!("negamax:easy:weights@{}", path).parse::<EngineSpec>();
+        let config = TournamentConfig::from_args(args(&["--engine", "negamax:easy:weights", "--opponent", "negamax:easy", "--params", path]));
+        let invalid = format!("negamax:easy:window@{}", path).parse::<EngineSpec>();
+        fs::remove_file(path).unwrap();
+
+        let spec = spec.unwrap();
+        assert_eq!(
+            (spec.params.as_ref().map(|(_, weights)| weights.0), spec.to_string().ends_with(path)), (Some(params), true),
+            "Expected the parameters of the file, got {:?}",
+            spec.params
+        );
+
+        let engines = config.unwrap().engines.map(|engine| engine.params.map(|(_, weights)| weights.0));
+        assert_eq!(
+            engines, [Some(params), None],
+            "Expected --params to apply to the weights evaluation only, got {:?}",
+            engines
+        );
+
+        assert!(
+            invalid.is_err(),
+            "Expected parameters of another evaluator to be rejected, but they weren't",
+        );
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = TournamentConfig::from_args(args(&["--engine", "mcts", "--games", "10", "--size", "8x7", "--sprt", "-5,5"])).unwrap();
+
+        assert_eq!(
+            (config.engines[0].engine, config.games, config.board.cols, config.elo0, config.elo1), (EngineKind::Mcts, 10, 8, -5.0, 5.0),
+            "Expected the tournament and board arguments to be parsed, got {:?}",
+            config
+        );
+
+        let invalid_args = [
+            &["--games", "0"][..], &["--engine"], &["--sprt", "5,0"], &["--players", "3"], &["--bogus"],
+            &["--difficulty", "easy"], &["--eval", "threat"], &["--no-ponder"],
+        ];
+
+        for invalid in invalid_args {
+            assert!(
+                TournamentConfig::from_args(args(invalid)).is_err(),
+                "Expected {:?} to be rejected, but it wasn't",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_openings() {
+        let openings = parse_openings("# center\n4 4\n\n 3 p3 \n");
+
+        assert_eq!(
+            openings, Ok(vec![vec![Move::Drop(3), Move::Drop(3)], vec![Move::Drop(2), Move::Pop(2)]]),
+            "Expected two openings, got {:?}",
+            openings
+        );
+
+        assert!(
+            parse_openings("4 x").is_err(),
+            "Expected an invalid opening to be rejected, but it wasn't",
+        );
+    }
+
+    #[test]
+    /// Tests the Elo difference, its error and the SPRT
+    fn test_standings() {
+        let even = Standings { wins: 40, draws: 20, losses: 40 };
+
+        assert!(
+            even.elo().abs() < 1e-9 && even.elo_error() > 0.0 && even.sprt(0.0, 10.0) == Sprt::Continue,
+            "Expected an even match to be undecided, got {}",
+            even
+        );
+
+        let strong = Standings { wins: 300, draws: 50, losses: 150 };
+
+        assert!(
+            strong.elo() > 50.0 && strong.sprt(0.0, 10.0) == Sprt::Pass,
+            "Expected a clearly stronger engine to pass, got {}",
+            strong
+        );
+
+        let weak = Standings { wins: 150, draws: 50, losses: 300 };
+
+        assert_eq!(
+            weak.sprt(0.0, 10.0), Sprt::Fail,
+            "Expected a clearly weaker engine to fail, got {}",
+            weak
+        );
+    }
+
+    #[test]
+    /// Tests that a game is played out and recorded
+    fn test_play_game() {
+        let config = Config { threads: 1, ..Config::default() };
+        let spec = "negamax:beginner".parse::<EngineSpec>().unwrap();
+        let opening = [Move::Drop(3), Move::Drop(3)];
+        let game = || {
+            let (mut a, mut b) = (spec.create(1, Rng::new(1)), spec.create(1, Rng::new(2)));
+            play_game(&config, &opening, [a.as_mut(), b.as_mut()]).unwrap()
+        };
+
+        let (board, winner) = game();
+
+        assert!(
+            board.is_over() && board.moves().starts_with(&opening) && winner == board.state().get_winner(),
+            "Expected a finished game starting with the opening, got {}",
+            board.notation()
+        );
+
+        let record = game_record(&config, [&spec, &spec], &opening, &board, winner);
+
+        assert!(
+            record.contains("[Opening \"4 4\"]") && record.contains(&board.notation()),
+            "Expected the opening and the moves to be recorded, got {}",
+            record
+        );
+
+        // the engines' random choices are replayed with the same generators
+        let replayed = game().0;
+
+        assert_eq!(
+            replayed.notation(), board.notation(),
+            "Expected the same game, got {}",
+            replayed.notation()
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+
+use crate::{
+    board::State,
+    config::Config,
+    eval::{evaluator::Weights, nn::{Network, HIDDEN, MAX_HIDDEN}, random::Rng, score::{Score, MAX_HEURISTIC}, search::SearchLimits, table::TranspositionTable},
+};
+
+/// The default number of random games generating positions
+pub const GAMES: usize = 2000;
+
+/// The default depth of the search labeling the positions
+pub const SOLVE_DEPTH: usize = 8;
+
+/// The default number of passes over all positions
+pub const EPOCHS: usize = 30;
+
+/// The default learning rate
+pub const RATE: f32 = 0.01;
+
+/// The default weights file the trained network is written to
+pub const NETWORK_FILE: &str = "network.txt";
+
+/// A searched position, with the input planes of the network (for
+/// the player to move) and its target: the result if the search
+/// decides it (1 for a win, -1 for a loss, 0 for a draw), else
+/// the searched heuristic score scaled to the same range
+#[derive(Clone, PartialEq, Debug)]
+pub struct Sample {
+    pub state: State,
+    pub input: Vec<f32>,
+    pub target: f32,
+}
+
+/// The configuration of the training
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainConfig {
+    /// the board (and rules) the network is trained for
+    pub board: Config,
+
+    pub games: usize,
+
+    /// the depth of the search labeling the positions
+    pub solve: usize,
+
+    pub hidden: usize,
+    pub epochs: usize,
+    pub rate: f32,
+
+    /// the weights file the trained network is written to
+    pub output: String,
+
+    /// the seed of the random games and the initial weights
+    pub seed: Option<u64>,
+}
+
+impl TrainConfig {
+    /// Parses the configuration from the given command line
+    /// arguments (excluding the program name and the subcommand)
+    ///
+    /// Supported arguments (in addition to the board arguments of `Config`):
+    ///     --games <n>             number of random games (default: 2000)
+    ///     --solve <depth>         depth of the search labeling the positions (default: 8)
+    ///     --hidden <n>            number of hidden units, 1 - 1024 (default: 32)
+    ///     --epochs <n>            passes over all positions (default: 30)
+    ///     --rate <x>              learning rate (default: 0.01)
+    ///     --output <file>         weights file to write (default: network.txt)
+    ///     --seed <n>              seed of the games and the initial weights
+    pub fn from_args<I>(args: I) -> Result<TrainConfig, String>
+    where
+        I: IntoIterator<Item = String>
+    {
+        let mut config = TrainConfig::default();
+        let mut board_args = vec![];
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            let number = |value: String| value.trim().parse::<usize>().map_err(|_| format!("invalid number '{}'", value));
+
+            match arg.as_str() {
+                "--games" => config.games = number(value()?)?.max(1),
+                "--solve" => config.solve = number(value()?)?.max(1),
+                "--hidden" => {
+                    config.hidden = number(value()?)?;
+                    if !(1 ..= MAX_HIDDEN).contains(&config.hidden) {
+                        return Err(format!("the number of hidden units must be between 1 and {}", MAX_HIDDEN));
+                    }
+                }
+                "--epochs" => config.epochs = number(value()?)?,
+                "--rate" => {
+                    let value = value()?;
+                    config.rate = value.trim().parse().ok().filter(|&rate: &f32| rate > 0.0)
+                        .ok_or(format!("invalid learning rate '{}'", value))?;
+                }
+                "--output" => config.output = value()?,
+                "--seed" => config.seed = Some(number(value()?)? as u64),
+                _ => board_args.push(arg),
+            }
+        }
+
+        config.board = Config::from_args(board_args)?;
+        Ok(config)
+    }
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        TrainConfig {
+            board: Config::default(),
+            games: GAMES,
+            solve: SOLVE_DEPTH,
+            hidden: HIDDEN,
+            epochs: EPOCHS,
+            rate: RATE,
+            output: NETWORK_FILE.to_string(),
+            seed: None,
+        }
+    }
+}
+
+/// Plays random games and returns every (distinct) position of them,
+/// labeled by a search of the configured depth with the `Weights`
+/// evaluator, so that the network learns the results of decided
+/// positions (including draws) and the searched scores of the others
+pub fn generate(config: &TrainConfig, rng: &mut Rng) -> Vec<Sample> {
+    let tt = TranspositionTable::with_entries(1 << 18);
+    let mut seen = HashSet::new();
+    let mut samples = vec![];
+
+    for _ in 0 .. config.games {
+        let mut state = config.board.state();
+
+        while !state.is_terminal() {
+            if seen.insert(state.canonical_hash()) {
+                let score = state.best(SearchLimits::depth(config.solve), state.player(), &Weights::DEFAULT, &tt, config.board.threads).score;
+                samples.push(Sample { input: Network::planes(&state, state.player()), state: state.clone(), target: target(score) });
+            }
+
+            let moves = state.get_valid();
+            state.play(moves[rng.below(moves.len())]);
+        }
+    }
+
+    samples
+}
+
+/// Returns the target of a searched position's score (see `Sample`)
+pub fn target(score: Score) -> f32 {
+    match score {
+        score if score.is_win() => 1.0,
+        score if score.is_loss() => -1.0,
+        score => (score.0 as f32 / 100.0 / MAX_HEURISTIC).clamp(-1.0, 1.0),
+    }
+}
+
+/// Trains the network on the samples for the given number of
+/// epochs, in a random order each. The given callback is called
+/// with every epoch's mean squared error.
+pub fn train(network: &mut Network, samples: &[Sample], epochs: usize, rate: f32, rng: &mut Rng, mut progress: impl FnMut(usize, f32)) {
+    let mut order = (0 .. samples.len()).collect::<Vec<_>>();
+
+    for epoch in 0 .. epochs {
+        // Fisher-Yates shuffle
+        for i in (1 .. order.len()).rev() {
+            order.swap(i, rng.below(i + 1));
+        }
+
+        let error = order.iter().map(|&i| network.train(&samples[i].input, samples[i].target, rate)).sum::<f32>();
+        progress(epoch + 1, error / samples.len().max(1) as f32);
+    }
+}
+
+/// Returns the share of the won or lost samples whose result
+/// the given evaluation predicts correctly by its sign
+pub fn accuracy(samples: &[Sample], evaluate: impl Fn(&State) -> f32) -> f32 {
+    let decided = samples.iter().filter(|sample| sample.target.abs() == 1.0).collect::<Vec<_>>();
+    let correct = decided
+        .iter()
+        .filter(|sample| evaluate(&sample.state) * sample.target > 0.0)
+        .count();
+
+    correct as f32 / decided.len().max(1) as f32
+}
+
+/// Generates the samples, trains a new network on them
+/// and writes it to the output file
+pub fn run(config: &TrainConfig) -> Result<Network, String> {
+    let mut rng = config.seed.map_or_else(Rng::from_time, Rng::new);
+
+    println!("playing {} games, searching to depth {}", config.games, config.solve);
+    let samples = generate(config, &mut rng);
+    println!("{} positions", samples.len());
+
+    let state = config.board.state();
+    let mut network = Network::new(state.rows(), state.cols(), config.hidden, &mut rng);
+
+    train(&mut network, &samples, config.epochs, config.rate, &mut rng, |epoch, error| {
+        println!("epoch {}: error {:.6}", epoch, error)
+    });
+
+    println!("accuracy {:.1}%", accuracy(&samples, |state| network.predict(state, state.player())) * 100.0);
+    network.save(&config.output)?;
+
+    Ok(network)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        board::{Move, Player},
+        eval::{difficulty::Difficulty, engine::{Engine, Negamax}, evaluator::Evaluator},
+        tournament::{play_game, Standings},
+    };
+
+    /// Returns an engine always playing the best move
+    /// found by searching to the given depth
+    fn engine(depth: usize, evaluator: &'static dyn Evaluator) -> Negamax {
+        let mut engine = Negamax::new(Difficulty::Hard, evaluator, Arc::new(TranspositionTable::new()), 1);
+        engine.engine.limits = SearchLimits::depth(depth);
+        engine
+    }
+
+    #[test]
+    /// Tests that draws and undecided positions are labeled
+    /// with targets between those of a win and a loss
+    fn test_target() {
+        let targets = [Score::win_in(3), Score::loss_in(2), Score::DRAW, Score(2500), Score(-50_000)].map(target);
+
+        assert_eq!(
+            targets, [1.0, -1.0, 0.0, 25.0 / MAX_HEURISTIC, -1.0],
+            "Expected the results and the scaled heuristic scores, got {:?}",
+            targets
+        );
+    }
+
+    #[test]
+    /// Tests that an engine with a trained network wins more games
+    /// than it loses against the same engine with the handcrafted
+    /// evaluation it was trained with, searching to the same depth
+    fn test_network_beats_handcrafted() {
+        let board = Config { rows: 4, cols: 5, win_length: 3, threads: 1, ..Config::default() };
+        let config = TrainConfig { board: board.clone(), games: 300, solve: 5, hidden: 16, ..TrainConfig::default() };
+        let mut rng = Rng::new(11);
+
+        let samples = generate(&config, &mut rng);
+        let mut network = Network::new(4, 5, config.hidden, &mut rng);
+        train(&mut network, &samples, 40, config.rate, &mut rng, |_, _| ());
+        let network: &'static Network = Box::leak(Box::new(network));
+
+        // every opening of two moves, played with both colors
+        let mut standings = Standings::default();
+        for opening in (0 .. 25).map(|i| [Move::Drop(i / 5), Move::Drop(i % 5)]) {
+            for color in [Player::Red, Player::Yellow] {
+                let (mut neural, mut handcrafted) = (engine(2, network), engine(2, &Weights::DEFAULT));
+                let engines: [&mut dyn Engine; 2] = match color {
+                    Player::Red => [&mut neural, &mut handcrafted],
+                    _ => [&mut handcrafted, &mut neural],
+                };
+
+                match play_game(&board, &opening, engines).unwrap().1 {
+                    Some(winner) if winner == color => standings.wins += 1,
+                    Some(_) => standings.losses += 1,
+                    None => standings.draws += 1,
+                }
+            }
+        }
+
+        assert!(
+            standings.wins > standings.losses,
+            "Expected the network to win more games than it loses, got {}",
+            standings
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use std::slice;
+
+use crate::{
+    board::{Board, State},
+    config::Config,
+    eval::{evaluator::Zero, params::{Features, Params, PARAMS}, random::Rng, search::SearchLimits, table::TranspositionTable},
+    tournament::{apply_params, play_game, EngineSpec},
+};
+
+/// The default number of self-play games generating positions
+pub const GAMES: usize = 200;
+
+/// The default number of random moves starting every game
+pub const RANDOM_PLIES: usize = 4;
+
+/// The default maximum number of rounds of the local search
+pub const ROUNDS: usize = 100;
+
+/// The default file the tuned parameters are written to
+pub const PARAMS_FILE: &str = "params.txt";
+
+/// A position labeled with the result of its game
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sample {
+    /// the features of the player to move minus the
+    /// ones of their opponent (see `State::features`)
+    pub features: Features,
+
+    /// the result for the player to move:
+    /// 1 for a win, 0.5 for a draw and 0 for a loss
+    pub result: f32,
+}
+
+impl Sample {
+    /// Returns the sample of the given position and result
+    /// (for the player to move) of a two-player game
+    pub fn new(state: &State, result: f32) -> Sample {
+        let player = state.player();
+        let features = state.features(player).minus(&state.features(player.other()));
+
+        Sample { features, result }
+    }
+}
+
+/// The configuration of the tuning
+#[derive(Clone, Debug, PartialEq)]
+pub struct TuneConfig {
+    /// the board (and rules) the games are played on
+    pub board: Config,
+
+    /// the engine playing both sides of the games
+    pub engine: EngineSpec,
+
+    pub games: usize,
+
+    /// the number of random moves starting every game
+    pub random_plies: usize,
+
+    /// the depth of the solver relabeling every position it
+    /// can solve with its exact result, if any
+    pub solve: Option<usize>,
+
+    /// the maximum number of rounds of the local search
+    pub rounds: usize,
+
+    /// the file the tuned parameters are written to
+    pub output: String,
+
+    /// the seed of the random moves
+    pub seed: Option<u64>,
+}
+
+impl TuneConfig {
+    /// Parses the configuration from the given command line
+    /// arguments (excluding the program name and the subcommand)
+    ///
+    /// Supported arguments (in addition to the board arguments of `Config`):
+    ///     --engine <spec>         the engine playing the games (default:
+    ///                             negamax:easy:weights, see `EngineSpec`)
+    ///     --games <n>             number of self-play games (default: 200)
+    ///     --random <n>            random moves starting every game (default: 4)
+    ///     --solve <depth>         label solvable positions with the solver
+    ///     --rounds <n>            maximum rounds of the local search (default: 100)
+    ///     --output <file>         parameter file to write (default: params.txt)
+    ///     --seed <n>              seed of the random moves
+    pub fn from_args<I>(args: I) -> Result<TuneConfig, String>
+    where
+        I: IntoIterator<Item = String>
+    {
+        let mut config = TuneConfig::default();
+        let mut board_args = vec![];
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            let number = |value: String| value.trim().parse::<usize>().map_err(|_| format!("invalid number '{}'", value));
+
+            match arg.as_str() {
+                "--engine" => config.engine = value()?.parse()?,
+                "--games" => {
+                    let value = value()?;
+                    config.games = value.trim().parse().ok().filter(|&games| games > 0)
+                        .ok_or(format!("invalid number of games '{}'", value))?;
+                }
+                "--random" => config.random_plies = number(value()?)?,
+                "--solve" => config.solve = Some(number(value()?)?.max(1)),
+                "--rounds" => config.rounds = number(value()?)?,
+                "--output" => config.output = value()?,
+                "--seed" => config.seed = Some(number(value()?)? as u64),
+                _ => board_args.push(arg),
+            }
+        }
+
+        config.board = Config::from_args(board_args)?;
+        if config.board.players != 2 {
+            return Err("parameters are tuned with two players".to_string());
+        }
+
+        apply_params(&config.board, slice::from_mut(&mut config.engine))?;
+
+        Ok(config)
+    }
+}
+
+impl Default for TuneConfig {
+    fn default() -> Self {
+        TuneConfig {
+            board: Config::default(),
+            engine: "negamax:easy:weights".parse().expect("a valid engine"),
+            games: GAMES,
+            random_plies: RANDOM_PLIES,
+            solve: None,
+            rounds: ROUNDS,
+            output: PARAMS_FILE.to_string(),
+            seed: None,
+        }
+    }
+}
+
+/// Plays self-play games, each starting with a few random moves,
+/// and returns every position (after the random moves) with the
+/// result of its game for the player to move
+pub fn generate(config: &TuneConfig, rng: &mut Rng) -> Result<Vec<(State, f32)>, String> {
+    let mut positions = vec![];
+
+    for _ in 0 .. config.games {
+        let mut opening = Board::from_state(config.board.state());
+        for _ in 0 .. config.random_plies {
+            let moves = opening.state().get_valid();
+            if opening.is_over() || moves.is_empty() {
+                break;
+            }
+            opening.play(moves[rng.below(moves.len())]);
+        }
+
+        let (mut a, mut b) = (
+            config.engine.create(config.board.threads, Rng::new(rng.next_u64())),
+            config.engine.create(config.board.threads, Rng::new(rng.next_u64())),
+        );
+        let (board, winner) = play_game(&config.board, opening.moves(), [a.as_mut(), b.as_mut()])?;
+
+        let mut state = opening.state().clone();
+        for &mv in &board.moves()[opening.moves().len() ..] {
+            let result = winner.map_or(0.5, |winner| if winner == state.player() { 1.0 } else { 0.0 });
+            positions.push((state.clone(), result));
+
+            state.play(mv);
+        }
+    }
+
+    Ok(positions)
+}
+
+/// Returns the samples of the given positions, relabeling every position
+/// the solver (a search with the `Zero` evaluator) decides within the
+/// given depth, if any, with its exact result
+pub fn label(config: &TuneConfig, positions: &[(State, f32)]) -> Vec<Sample> {
+    let tt = TranspositionTable::with_entries(1 << 16);
+
+    positions
+        .iter()
+        .map(|(state, result)| {
+            let result = match config.solve {
+                Some(depth) => {
+                    let score = state.best(SearchLimits::depth(depth), state.player(), &Zero, &tt, config.board.threads).score;
+                    if score.is_win() { 1.0 } else if score.is_loss() { 0.0 } else { *result }
+                }
+                None => *result,
+            };
+
+            Sample::new(state, result)
+        })
+        .collect()
+}
+
+/// Returns the expected result of an evaluation, scaled by `k`
+fn sigmoid(k: f32, eval: f32) -> f32 {
+    1.0 / (1.0 + (-k * eval).exp())
+}
+
+/// Returns the mean squared error of the expected results
+/// of the given parameters (scaled by `k`) and the samples
+pub fn error(samples: &[Sample], params: &Params, k: f32) -> f64 {
+    let sum = samples
+        .iter()
+        .map(|sample| (sample.result - sigmoid(k, params.evaluate(&sample.features))) as f64)
+        .map(|diff| diff * diff)
+        .sum::<f64>();
+
+    sum / samples.len().max(1) as f64
+}
+
+/// Returns the scale `k` of the expected results
+/// best fitting the given parameters to the samples
+pub fn fit_scale(samples: &[Sample], params: &Params) -> f32 {
+    (0 .. 100)
+        .map(|i| 0.001 * 1.1f32.powi(i))
+        .min_by(|&a, &b| error(samples, params, a).total_cmp(&error(samples, params, b)))
+        .expect("there are scales to try")
+}
+
+/// Tunes the given parameters by a local search ("Texel tuning"): every
+/// round, each parameter is moved up or down by its step size as long as
+/// that lowers the error. A round without improvement halves the step
+/// sizes. The given callback is called with every round's error.
+pub fn tune(samples: &[Sample], params: Params, k: f32, rounds: usize, mut progress: impl FnMut(usize, f64)) -> Params {
+    let mut values = params.values();
+    let mut steps = values.map(|value| (value.abs() * 0.1).max(0.05));
+    let mut best = error(samples, &params, k);
+
+    for round in 0 .. rounds {
+        let mut improved = false;
+
+        for i in 0 .. PARAMS {
+            for direction in [1.0, -1.0] {
+                let mut candidate = values;
+                candidate[i] += direction * steps[i];
+
+                let candidate_error = error(samples, &Params::from_values(candidate), k);
+                if candidate_error < best {
+                    (values, best, improved) = (candidate, candidate_error, true);
+                    break;
+                }
+            }
+        }
+
+        progress(round + 1, best);
+
+        if !improved {
+            steps = steps.map(|step| step / 2.0);
+            if steps.iter().all(|&step| step < 1e-3) {
+                break;
+            }
+        }
+    }
+
+    Params::from_values(values)
+}
+
+/// Generates the samples, tunes the parameters of the engine's
+/// weights evaluation (or of `--params`, or the default ones)
+/// and writes them to the output file
+pub fn run(config: &TuneConfig) -> Result<Params, String> {
+    let mut rng = config.seed.map_or_else(Rng::from_time, Rng::new);
+
+    println!("playing {} games ({})", config.games, config.engine);
+    let positions = generate(config, &mut rng)?;
+
+    if let Some(depth) = config.solve {
+        println!("solving {} positions to depth {}", positions.len(), depth);
+    }
+    let samples = label(config, &positions);
+
+    let params = match &config.engine.params {
+        Some((_, weights)) => weights.0,
+        None => config.board.load_params()?.unwrap_or_default(),
+    };
+    let k = fit_scale(&samples, &params);
+    println!("{} positions, scale {:.4}, error {:.6}", samples.len(), k, error(&samples, &params, k));
+
+    let tuned = tune(&samples, params, k, config.rounds, |round, error| println!("round {}: error {:.6}", round, error));
+    tuned.save(&config.output)?;
+
+    print!("{}", tuned);
+    Ok(tuned)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = TuneConfig::from_args(args(&["--games", "5", "--solve", "8", "--connect", "3", "--output", "tuned.txt"])).unwrap();
+
+        assert_eq!(
+            (config.games, config.solve, config.board.win_length, config.output.as_str()), (5, Some(8), 3, "tuned.txt"),
+            "Expected the tuning and board arguments to be parsed, got {:?}",
+            config
+        );
+
+        for invalid in [&["--games", "x"][..], &["--games", "0"], &["--engine", "bogus"], &["--players", "3"]] {
+            assert!(
+                TuneConfig::from_args(args(invalid)).is_err(),
+                "Expected {:?} to be rejected, but it wasn't",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that every position of the games is labeled
+    /// from the perspective of its player to move
+    fn test_generate() {
+        let config = TuneConfig { games: 2, engine: "negamax:beginner".parse().unwrap(), board: Config { threads: 1, ..Config::default() }, ..TuneConfig::default() };
+        let positions = generate(&config, &mut Rng::new(5)).unwrap();
+
+        assert!(
+            !positions.is_empty() && positions.iter().all(|(state, _)| !state.is_terminal()),
+            "Expected positions of unfinished games, got {}",
+            positions.len()
+        );
+
+        // the player to move alternates, and so does the result
+        for pair in positions.windows(2) {
+            let ((a, a_result), (b, b_result)) = (&pair[0], &pair[1]);
+            if a.player() != b.player() && a.moves() < b.moves() && *a_result != 0.5 {
+                assert_eq!(
+                    *a_result, 1.0 - *b_result,
+                    "Expected the results of consecutive positions to be opposite, got {} and {}",
+                    a_result, b_result
+                );
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that tuning lowers the error and finds
+    /// the feature deciding the (synthetic) results
+    fn test_tune() {
+        let samples = (0 .. 200)
+            .map(|i| {
+                let streak = (i % 7) as f32 - 3.0;
+                let features = Features { position: (i % 5) as f32, streaks: [streak, 0.0, 0.0, 0.0, 0.0], captured: 0.0 };
+                Sample { features, result: if streak > 0.0 { 1.0 } else if streak < 0.0 { 0.0 } else { 0.5 } }
+            })
+            .collect::<Vec<_>>();
+
+        let params = Params { position: 1.0, streaks: [0.0; 5], captured: 0.0 };
+        let tuned = tune(&samples, params, 1.0, 50, |_, _| ());
+
+        assert!(
+            error(&samples, &tuned, 1.0) < error(&samples, &params, 1.0),
+            "Expected a lower error, got {:?}",
+            tuned
+        );
+
+        assert!(
+            tuned.streaks[0] > tuned.position.abs(),
+            "Expected the deciding feature to weigh the most, got {:?}",
+            tuned
+        );
+    }
+}